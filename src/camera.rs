@@ -26,29 +26,35 @@ impl PerspectiveCamera {
         }
     }
 
+    /// Generates the projection matrix. Needs to be called after the fov, aspect or clipping
+    /// planes are mutated.
+    /// The camera looks towards +z. Points inside the view frustum end up in clip space with
+    /// -w <= x <= w, -w <= y <= w and 0 <= z <= w.
     pub fn generate_projection_matrix(&mut self) {
+        let scale_y = 1. / (self.fov / 2.).tan();
         self.projection_matrix = Matrix44 {
             data: vec![
-                1. / ((self.fov / 2.).tan() * self.aspect),
+                scale_y / self.aspect,
                 0.,
                 0.,
                 0.,
                 0.,
-                1. / (self.fov / 2.).tan(),
+                scale_y,
                 0.,
                 0.,
                 0.,
                 0.,
-                -self.z_far / (self.z_far - self.z_near),
-                1.,
+                self.z_far / (self.z_far - self.z_near),
+                -self.z_far * self.z_near / (self.z_far - self.z_near),
                 0.,
                 0.,
-                -self.z_far * self.z_near / (self.z_far - self.z_near),
+                1.,
                 0.,
             ],
         }
     }
 
+    /// Returns the w component of a point in clip space (its depth in view space)
     pub fn get_w(&self, point: &Vector3, transformation: &Transformation) -> f32 {
         let point = transformation.transformed(point) - self.position; // TODO: cache
                                                                        // fully transformed?
@@ -58,26 +64,28 @@ impl PerspectiveCamera {
             + self.projection_matrix.get(3, 3)
     }
 
-    pub fn project_point(&self, point: &Vector3, transformation: &Transformation) -> Vector3 {
-        let point = transformation.transformed(point) - self.position;
-        let mut projected = self
-            .projection_matrix
-            .multiply_vec4(&Vector4 {
-                x: point.x,
-                y: point.y,
-                z: point.z,
-                w: 1.,
-            })
-            .to_vector3();
-        if point.z < 0. {
-            projected.z *= -1.;
-        }
-        projected
+    /// Transforms a point into (homogeneous) clip space
+    pub fn project_point(&self, point: &Vector3, transformation: &Transformation) -> Vector4 {
+        self.project(&transformation.transformed(point))
+    }
+
+    /// Transforms a point that is already in world space into clip space
+    pub fn project(&self, point: &Vector3) -> Vector4 {
+        let point = *point - self.position;
+        self.projection_matrix.multiply_vec4(&Vector4 {
+            x: point.x,
+            y: point.y,
+            z: point.z,
+            w: 1.,
+        })
     }
 
-    pub fn to_ndc(&self, mut projected: Vector3) -> Vector3 {
-        projected.x = ((-projected.x + 1.) * self.size.x) / 2.;
-        projected.y = ((projected.y + 1.) * self.size.y) / 2.;
+    /// Converts a point in clip space to screen coordinates (z is kept as the depth, from 0 on
+    /// the near plane to 1 on the far plane)
+    pub fn to_ndc(&self, projected: Vector4) -> Vector3 {
+        let mut projected = projected.to_vector3();
+        projected.x = ((projected.x + 1.) * self.size.x) / 2.;
+        projected.y = ((-projected.y + 1.) * self.size.y) / 2.;
         projected
     }
 }
@@ -0,0 +1,84 @@
+use crate::{graphics::Attributes, math::Vector4};
+
+/// A vertex in clip space along with the attributes that need to be interpolated when the
+/// vertex gets cut by a clipping plane
+#[derive(Copy, Clone)]
+pub struct ClipVertex {
+    pub position: Vector4,
+    pub attributes: Attributes,
+}
+
+impl ClipVertex {
+    pub fn new(position: Vector4, attributes: Attributes) -> ClipVertex {
+        ClipVertex {
+            position,
+            attributes,
+        }
+    }
+
+    /// Linearly interpolates between two vertices. This is correct in clip space, where
+    /// perspective division hasn't happened yet.
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        ClipVertex {
+            position: self.position + (other.position - self.position) * t,
+            attributes: self.attributes.lerp(&other.attributes, t),
+        }
+    }
+}
+
+/// Signed distances to the six planes of the view frustum (near, far, left, right, bottom, top).
+/// A point is inside a plane when its distance is positive.
+const PLANES: [fn(&Vector4) -> f32; 6] = [
+    |p| p.z,
+    |p| p.w - p.z,
+    |p| p.w + p.x,
+    |p| p.w - p.x,
+    |p| p.w + p.y,
+    |p| p.w - p.y,
+];
+
+/// Whether a point in clip space is inside of the view frustum
+pub fn point_visible(position: &Vector4) -> bool {
+    PLANES.iter().all(|plane| plane(position) >= 0.)
+}
+
+/// Clips a triangle against the view frustum (Sutherland–Hodgman).
+/// Returns a convex polygon with the same winding as the triangle, which is empty if the triangle
+/// is entirely outside of the frustum. The polygon can be drawn as a triangle fan.
+pub fn clip_triangle(triangle: [ClipVertex; 3]) -> Vec<ClipVertex> {
+    // Fast paths: entirely inside or entirely outside of one plane
+    let mut inside = true;
+    for plane in PLANES {
+        let distances = triangle.map(|vertex| plane(&vertex.position));
+        if distances.iter().all(|distance| *distance < 0.) {
+            return Vec::new();
+        }
+        inside &= distances.iter().all(|distance| *distance >= 0.);
+    }
+    if inside {
+        return triangle.to_vec();
+    }
+
+    let mut polygon = triangle.to_vec();
+    for plane in PLANES {
+        if polygon.is_empty() {
+            break;
+        }
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, current) in polygon.iter().enumerate() {
+            let next = &polygon[(i + 1) % polygon.len()];
+            let current_distance = plane(&current.position);
+            let next_distance = plane(&next.position);
+            if current_distance >= 0. {
+                clipped.push(*current);
+            }
+            // The edge crosses the plane: add the intersection
+            if (current_distance >= 0.) != (next_distance >= 0.) {
+                let t = current_distance / (current_distance - next_distance);
+                clipped.push(current.lerp(next, t));
+            }
+        }
+        polygon = clipped;
+    }
+    polygon
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{graphics::Color, material::Material, mesh::Mesh};
+
+impl Mesh {
+    /// Writes the mesh to a binary .stl file, in its own space (its transformation isn't applied)
+    pub fn write_stl<P: AsRef<Path>>(&self, file_path: P) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(file_path)?);
+        let mut header = [0u8; 80];
+        let name = b"renderer";
+        header[..name.len()].copy_from_slice(name);
+        file.write_all(&header)?;
+        file.write_all(&(self.faces.len() as u32).to_le_bytes())?;
+        for face in &self.faces {
+            for vector in [
+                face.normal,
+                self.vertices[face.a],
+                self.vertices[face.b],
+                self.vertices[face.c],
+            ] {
+                for value in [vector.x, vector.y, vector.z] {
+                    file.write_all(&value.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, unused
+            file.write_all(&[0, 0])?;
+        }
+        file.flush()
+    }
+
+    /// Writes the mesh to an .obj file, in its own space (its transformation isn't applied).
+    /// If it has materials, they are written to an .mtl file with the same name next to it.
+    /// Faces without a material that come after faces with one get a white default material.
+    pub fn write_obj<P: AsRef<Path>>(&self, file_path: P) -> std::io::Result<()> {
+        let path = file_path.as_ref();
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut materials = self.materials.clone();
+        let needs_default = self
+            .primitives
+            .iter()
+            .any(|primitive| primitive.material.is_none() && primitive.faces.start > 0);
+        // After the mesh's own materials, with a name none of them has
+        let default = materials.len();
+        if !materials.is_empty() && needs_default {
+            let mut name = String::from("default");
+            while materials.iter().any(|material| material.name == name) {
+                name.push('_');
+            }
+            materials.push(Material::new(&name));
+        }
+        if !materials.is_empty() {
+            let mtl_path = path.with_extension("mtl");
+            write_mtl(&materials, &mtl_path)?;
+            let name = mtl_path.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(file, "mtllib {}", name)?;
+        }
+
+        for vertex in &self.vertices {
+            writeln!(file, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+        }
+        // The loader flips v
+        for uv in &self.uvs {
+            writeln!(file, "vt {} {}", uv.x, 1. - uv.y)?;
+        }
+        for normal in &self.normals {
+            writeln!(file, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+
+        // Faces without a smoothing group can only be written before the first s statement
+        let mut smoothing_group = None;
+        for (i, face) in self.faces.iter().enumerate() {
+            for group in self.groups.iter().filter(|group| group.faces.start == i) {
+                writeln!(file, "o {}", group.name)?;
+            }
+            for primitive in self.primitives.iter().filter(|p| p.faces.start == i) {
+                match primitive.material {
+                    Some(material) => writeln!(file, "usemtl {}", materials[material].name)?,
+                    // Faces only go without a material before the first usemtl
+                    None if i > 0 && default < materials.len() => {
+                        writeln!(file, "usemtl {}", materials[default].name)?
+                    }
+                    None => {}
+                }
+            }
+            if face.smoothing_group.is_some() && face.smoothing_group != smoothing_group {
+                smoothing_group = face.smoothing_group;
+                match face.smoothing_group {
+                    Some(0) => writeln!(file, "s off")?,
+                    Some(group) => writeln!(file, "s {}", group)?,
+                    None => {}
+                }
+            }
+            // Indices start at 1
+            let corner = |vertex: usize, uv: usize, normal: Option<usize>| match normal {
+                Some(normal) => format!("{}/{}/{}", vertex + 1, uv + 1, normal + 1),
+                None => format!("{}/{}", vertex + 1, uv + 1),
+            };
+            writeln!(
+                file,
+                "f {} {} {}",
+                corner(face.a, face.uva, face.na),
+                corner(face.b, face.uvb, face.nb),
+                corner(face.c, face.uvc, face.nc)
+            )?;
+        }
+        file.flush()
+    }
+}
+
+/// Writes materials to an .mtl file. Texture paths are made relative to the file when they are in
+/// its directory, and absolute otherwise. Textures without a file (embedded in a glTF file...) are
+/// saved next to it as <file name>_<material index>_<keyword>.png.
+fn write_mtl(materials: &[Material], path: &Path) -> std::io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut file = BufWriter::new(File::create(path)?);
+    let color = |color: Color| {
+        format!(
+            "{} {} {}",
+            color.0 as f32 / 255.,
+            color.1 as f32 / 255.,
+            color.2 as f32 / 255.
+        )
+    };
+    let texture = |texture: &Path| -> std::io::Result<PathBuf> {
+        Ok(match texture.strip_prefix(directory) {
+            Ok(relative) => relative.to_path_buf(),
+            _ => std::env::current_dir()?.join(texture),
+        })
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    for (i, material) in materials.iter().enumerate() {
+        writeln!(file, "newmtl {}", material.name)?;
+        writeln!(file, "Ka {}", color(material.ambient))?;
+        writeln!(file, "Kd {}", color(material.diffuse))?;
+        writeln!(file, "Ks {}", color(material.specular))?;
+        writeln!(file, "Ns {}", material.shininess)?;
+        writeln!(file, "d {}", material.opacity)?;
+        for (keyword, map, decoded) in [
+            ("map_Kd", &material.diffuse_map, &material.diffuse_texture),
+            ("map_Bump", &material.bump_map, &None),
+            ("map_d", &material.alpha_map, &material.alpha_texture),
+        ] {
+            match (map, decoded) {
+                (Some(map), _) => writeln!(file, "{} {}", keyword, texture(map)?.display())?,
+                (None, Some(decoded)) => {
+                    let name = format!("{}_{}_{}.png", stem, i, keyword);
+                    decoded
+                        .save(directory.join(&name))
+                        .map_err(std::io::Error::other)?;
+                    writeln!(file, "{} {}", keyword, name)?;
+                }
+                (None, None) => {}
+            }
+        }
+        writeln!(file)?;
+    }
+    file.flush()
+}
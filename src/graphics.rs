@@ -1,21 +1,351 @@
-extern crate sdl2;
+use std::path::Path;
 
-use sdl2::video::Window;
+use image::{GrayImage, ImageResult, RgbImage};
 
 use crate::{
     camera::PerspectiveCamera,
-    math::{self, Vector2, Vector3},
+    math::{Vector2, Vector3},
+    postprocess,
+    presenter::{Event, Presenter},
     shaders::Shader,
+    world::World,
 };
 
+/// Red, green, blue and alpha, from 0 to 255. An alpha of 255 is opaque, 0 is fully transparent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Color(pub u8, pub u8, pub u8, pub u8);
+
+impl Color {
+    /// Creates an opaque color
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color(r, g, b, 255)
+    }
+
+    /// Returns the same color with another alpha
+    pub fn with_alpha(self, alpha: u8) -> Color {
+        Color(self.0, self.1, self.2, alpha)
+    }
+
+    /// Linearly interpolates between two colors
+    pub fn lerp(self, other: Color, t: f32) -> Color {
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color(
+            mix(self.0, other.0),
+            mix(self.1, other.1),
+            mix(self.2, other.2),
+            mix(self.3, other.3),
+        )
+    }
+}
+
+/// Attributes of a vertex that are interpolated across triangles and given to shaders
 #[derive(Copy, Clone)]
-pub struct Color(pub u8, pub u8, pub u8);
+pub struct Attributes {
+    pub uv: Vector2,
+    /// White if the mesh doesn't have vertex colors
+    pub color: Color,
+    /// Normal, in world space
+    pub normal: Vector3,
+    /// Position, in world space
+    pub position: Vector3,
+    /// Lighting computed at the vertex, for Gouraud shading (1 otherwise)
+    pub brightness: f32,
+    /// Custom values for shaders to pass from their vertex stage to their fragment stage (0 by
+    /// default). Shaders use as many of them as they need, up to MAX_VARYINGS.
+    pub varyings: [f32; MAX_VARYINGS],
+}
+
+/// At the origin, white, without a normal and fully lit
+impl Default for Attributes {
+    fn default() -> Attributes {
+        Attributes {
+            uv: Vector2::new(0., 0.),
+            color: Color::rgb(255, 255, 255),
+            normal: Vector3::new(0., 0., 0.),
+            position: Vector3::new(0., 0., 0.),
+            brightness: 1.,
+            varyings: [0.; MAX_VARYINGS],
+        }
+    }
+}
+
+/// How the (perspective corrected) barycentric coordinates of a fragment change from one pixel to
+/// the next, so shaders can tell how fast attributes change across the screen (see
+/// Attributes::uv_derivatives)
+#[derive(Copy, Clone)]
+pub struct Derivatives {
+    /// To the pixel on the right
+    pub dx: Vector3,
+    /// To the pixel below
+    pub dy: Vector3,
+}
+
+/// Number of custom values in Attributes. They are a fixed size array so that attributes stay
+/// Copy and don't allocate for every fragment; shaders that need more can raise this, at the cost
+/// of interpolating all of them for every vertex and fragment.
+pub const MAX_VARYINGS: usize = 8;
+
+impl Derivatives {
+    /// Attributes that don't change across the screen (points...)
+    pub fn zero() -> Derivatives {
+        Derivatives {
+            dx: Vector3::new(0., 0., 0.),
+            dy: Vector3::new(0., 0., 0.),
+        }
+    }
+}
+
+impl Attributes {
+    /// Linearly interpolates between the attributes of two vertices
+    pub fn lerp(&self, other: &Attributes, t: f32) -> Attributes {
+        Attributes {
+            uv: self.uv + (other.uv - self.uv) * t,
+            color: self.color.lerp(other.color, t),
+            normal: self.normal + (other.normal - self.normal) * t,
+            position: self.position + (other.position - self.position) * t,
+            brightness: self.brightness + (other.brightness - self.brightness) * t,
+            varyings: std::array::from_fn(|i| {
+                self.varyings[i] + (other.varyings[i] - self.varyings[i]) * t
+            }),
+        }
+    }
+
+    /// Returns how much the UVs of a triangle change from one pixel to the next, horizontally
+    /// then vertically
+    pub fn uv_derivatives(derivatives: &Derivatives, vertices: &[Attributes; 3]) -> [Vector2; 2] {
+        let [a, b, c] = vertices;
+        [derivatives.dx, derivatives.dy].map(|d| a.uv * d.x + b.uv * d.y + c.uv * d.z)
+    }
+
+    /// Interpolates the attributes of the vertices of a triangle at a point given by its
+    /// barycentric coordinates. Normals aren't normalised.
+    pub fn interpolate(barycentric: &Vector3, vertices: &[Attributes; 3]) -> Attributes {
+        let [a, b, c] = vertices;
+        let (x, y, z) = (barycentric.x, barycentric.y, barycentric.z);
+        let mix = |a: u8, b: u8, c: u8| {
+            (a as f32 * x + b as f32 * y + c as f32 * z)
+                .round()
+                .clamp(0., 255.) as u8
+        };
+        Attributes {
+            uv: a.uv * x + b.uv * y + c.uv * z,
+            color: Color(
+                mix(a.color.0, b.color.0, c.color.0),
+                mix(a.color.1, b.color.1, c.color.1),
+                mix(a.color.2, b.color.2, c.color.2),
+                mix(a.color.3, b.color.3, c.color.3),
+            ),
+            normal: a.normal * x + b.normal * y + c.normal * z,
+            position: a.position * x + b.position * y + c.position * z,
+            brightness: a.brightness * x + b.brightness * y + c.brightness * z,
+            varyings: std::array::from_fn(|i| {
+                a.varyings[i] * x + b.varyings[i] * y + c.varyings[i] * z
+            }),
+        }
+    }
+}
+
+/// How vertex attributes (UVs...) are interpolated across triangles
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interpolation {
+    /// Interpolates attr/w and 1/w in screen space, which is correct under perspective
+    PerspectiveCorrect,
+    /// Interpolates attributes linearly in screen space. Textures will swim, like on the PS1.
+    Affine,
+}
+
+/// Where lighting is evaluated
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Lighting {
+    /// Once per face, with its normal (see Shader::calculate_lighting)
+    Flat,
+    /// Once per vertex, with its normal, and interpolated across faces
+    Gouraud,
+    /// Once per pixel, with interpolated normals and positions: diffuse lighting, and specular
+    /// highlights from the reflection of the light around the normal
+    Phong,
+    /// Like Phong, with highlights from the half vector between the light and the view direction
+    BlinnPhong,
+}
+
+/// How the alpha of the fragments of a shader is used (see Shader::alpha_mode)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    Opaque,
+    /// Fragments with an alpha below the cutoff (from 0 to 1) are discarded, the others are
+    /// opaque. For cutouts: foliage, fences, decals...
+    Mask(f32),
+    /// Fragments are blended with the color behind them, and don't hide what is drawn after them
+    /// (they don't write depth). For glass, overlays...
+    Blend(Blend),
+}
+
+/// Blend equation, combining the color of a fragment (source) with the color already in the
+/// buffer (destination), like glBlendEquation and glBlendFunc
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Blend {
+    pub equation: BlendEquation,
+    /// Factor the source is multiplied by
+    pub source: BlendFactor,
+    /// Factor the destination is multiplied by
+    pub destination: BlendFactor,
+}
+
+/// How the weighted source and destination are combined
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendEquation {
+    /// source + destination
+    Add,
+    /// source - destination
+    Subtract,
+    /// destination - source
+    ReverseSubtract,
+    /// Smallest of the source and destination, ignoring the factors
+    Min,
+    /// Largest of the source and destination, ignoring the factors
+    Max,
+}
+
+/// What a color is multiplied by before blending. The buffer is opaque, so there aren't any factors
+/// for the alpha of the destination.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SourceColor,
+    OneMinusSourceColor,
+    DestinationColor,
+    OneMinusDestinationColor,
+    SourceAlpha,
+    OneMinusSourceAlpha,
+}
+
+impl Blend {
+    /// Transparency: the source is drawn over the destination, weighted by its alpha
+    pub const ALPHA: Blend = Blend::new(
+        BlendEquation::Add,
+        BlendFactor::SourceAlpha,
+        BlendFactor::OneMinusSourceAlpha,
+    );
+    /// The source is added to the destination, weighted by its alpha (glows, particles...)
+    pub const ADDITIVE: Blend = Blend::new(
+        BlendEquation::Add,
+        BlendFactor::SourceAlpha,
+        BlendFactor::One,
+    );
+    /// The destination is tinted by the source
+    pub const MULTIPLY: Blend = Blend::new(
+        BlendEquation::Add,
+        BlendFactor::DestinationColor,
+        BlendFactor::Zero,
+    );
+
+    pub const fn new(
+        equation: BlendEquation,
+        source: BlendFactor,
+        destination: BlendFactor,
+    ) -> Blend {
+        Blend {
+            equation,
+            source,
+            destination,
+        }
+    }
+
+    /// Blends a fragment's color with the color behind it. The result is opaque.
+    pub fn apply(&self, source: Color, destination: Color) -> Color {
+        let channels = |color: Color| [color.0, color.1, color.2].map(|x| x as f32 / 255.);
+        let (src, dst) = (channels(source), channels(destination));
+        let alpha = source.3 as f32 / 255.;
+        let factor = |factor: BlendFactor, i: usize| match factor {
+            BlendFactor::Zero => 0.,
+            BlendFactor::One => 1.,
+            BlendFactor::SourceColor => src[i],
+            BlendFactor::OneMinusSourceColor => 1. - src[i],
+            BlendFactor::DestinationColor => dst[i],
+            BlendFactor::OneMinusDestinationColor => 1. - dst[i],
+            BlendFactor::SourceAlpha => alpha,
+            BlendFactor::OneMinusSourceAlpha => 1. - alpha,
+        };
+        let [r, g, b] = [0, 1, 2].map(|i| {
+            let s = src[i] * factor(self.source, i);
+            let d = dst[i] * factor(self.destination, i);
+            let value = match self.equation {
+                BlendEquation::Add => s + d,
+                BlendEquation::Subtract => s - d,
+                BlendEquation::ReverseSubtract => d - s,
+                BlendEquation::Min => src[i].min(dst[i]),
+                BlendEquation::Max => src[i].max(dst[i]),
+            };
+            (value.clamp(0., 1.) * 255.).round() as u8
+        });
+        Color::rgb(r, g, b)
+    }
+}
+
+/// Multisample anti-aliasing: coverage and depth are computed for several samples per pixel, but
+/// pixels are only shaded once
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Msaa {
+    Off,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa {
+    /// Returns the number of samples per pixel
+    pub fn samples(self) -> usize {
+        self.pattern().len()
+    }
+
+    /// Sample positions relative to the pixel center, in sub-pixel units (the standard Direct3D
+    /// patterns)
+    fn pattern(self) -> &'static [[i64; 2]] {
+        const S: i64 = SUBPIXEL_ONE / 16;
+        match self {
+            Msaa::Off => &[[0, 0]],
+            Msaa::X2 => &[[4 * S, 4 * S], [-4 * S, -4 * S]],
+            Msaa::X4 => &[
+                [-2 * S, -6 * S],
+                [6 * S, -2 * S],
+                [-6 * S, 2 * S],
+                [2 * S, 6 * S],
+            ],
+            Msaa::X8 => &[
+                [S, -3 * S],
+                [-S, 3 * S],
+                [5 * S, S],
+                [-3 * S, -5 * S],
+                [-5 * S, 5 * S],
+                [-7 * S, -S],
+                [3 * S, 7 * S],
+                [7 * S, -7 * S],
+            ],
+        }
+    }
+}
 
 pub struct Renderer {
     pub clear_color: Color,
-    canvas: sdl2::render::Canvas<Window>,
-    event_pump: sdl2::EventPump,
+    pub interpolation: Interpolation,
+    pub lighting: Lighting,
+    /// Number of threads used to rasterize batches of triangles (see draw_triangles)
+    pub threads: usize,
+    /// Applies an FXAA-style post-processing pass when the frame is resolved
+    pub fxaa: bool,
+    presenter: Option<Box<dyn Presenter>>,
     running: bool,
+    msaa: Msaa,
+    /// Set when the color buffer is up to date with what has been drawn (see resolve)
+    resolved: bool,
+    color_buffer: Box<[u32]>,
+    /// Colors of every sample when MSAA is on (empty otherwise, samples are the pixels)
+    sample_buffer: Box<[u32]>,
+    /// The color buffer after post-processing, when FXAA is on (empty otherwise). It is kept
+    /// apart so that resolving again doesn't post-process the same pixels twice.
+    post_buffer: Box<[u32]>,
     depth_buffer: Box<[f32]>,
     empty_buffer: Box<[f32]>,
     width: u32,
@@ -23,26 +353,76 @@ pub struct Renderer {
 }
 
 impl Renderer {
-    /// Creates a new Renderer
+    /// Creates a new Renderer that presents to an SDL window
+    #[cfg(feature = "sdl")]
     pub fn new(title: &str, width: u32, height: u32) -> Renderer {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let window = video_subsystem
-            .window(title, width, height)
-            .position_centered()
-            .resizable()
-            .build()
-            .unwrap();
-
-        Renderer {
-            clear_color: Color(0, 0, 0),
-            canvas: window.into_canvas().build().unwrap(),
-            event_pump: sdl_context.event_pump().unwrap(),
+        Renderer::with_presenter(
+            Box::new(crate::presenter::SdlPresenter::new(title, width, height)),
+            width,
+            height,
+        )
+    }
+
+    /// Creates a new Renderer that only draws to its in-memory buffers (no window/video subsystem)
+    pub fn headless(width: u32, height: u32) -> Renderer {
+        Renderer::build(None, width, height)
+    }
+
+    /// Creates a new Renderer that presents frames with a custom Presenter
+    pub fn with_presenter(presenter: Box<dyn Presenter>, width: u32, height: u32) -> Renderer {
+        Renderer::build(Some(presenter), width, height)
+    }
+
+    fn build(presenter: Option<Box<dyn Presenter>>, width: u32, height: u32) -> Renderer {
+        let mut renderer = Renderer {
+            clear_color: Color::rgb(0, 0, 0),
+            interpolation: Interpolation::PerspectiveCorrect,
+            lighting: Lighting::Flat,
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            fxaa: false,
+            presenter,
             running: true,
+            msaa: Msaa::Off,
+            resolved: true,
+            color_buffer: Box::new([]),
+            sample_buffer: Box::new([]),
+            post_buffer: Box::new([]),
             depth_buffer: Box::new([]),
-            empty_buffer: vec![0.; (width * height) as usize].into_boxed_slice(),
+            empty_buffer: Box::new([]),
             width,
             height,
+        };
+        renderer.allocate_buffers();
+        renderer
+    }
+
+    /// (Re)allocates the buffers for the current size and MSAA setting
+    fn allocate_buffers(&mut self) {
+        let pixels = (self.width * self.height) as usize;
+        let samples = pixels * self.msaa.samples();
+        self.color_buffer = vec![self.clear_color.into(); pixels].into_boxed_slice();
+        self.sample_buffer = if self.msaa == Msaa::Off {
+            Box::new([])
+        } else {
+            vec![self.clear_color.into(); samples].into_boxed_slice()
+        };
+        self.post_buffer = Box::new([]);
+        self.depth_buffer = vec![f32::INFINITY; samples].into_boxed_slice();
+        self.empty_buffer = vec![f32::INFINITY; samples].into_boxed_slice();
+        self.resolved = true;
+    }
+
+    /// Returns the current MSAA setting
+    pub fn msaa(&self) -> Msaa {
+        self.msaa
+    }
+
+    /// Changes the number of samples per pixel. The buffers' contents are lost until the next
+    /// clear.
+    pub fn set_msaa(&mut self, msaa: Msaa) {
+        if msaa != self.msaa {
+            self.msaa = msaa;
+            self.allocate_buffers();
         }
     }
 
@@ -51,169 +431,603 @@ impl Renderer {
         self.running
     }
 
+    /// Returns the width of the buffers in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the buffers in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the color buffer (row-major, width * height pixels packed as 0x00RRGGBB).
+    /// With MSAA or FXAA on, this is the last resolved frame (see resolve).
+    pub fn color_buffer(&self) -> &[u32] {
+        if self.fxaa && !self.post_buffer.is_empty() {
+            &self.post_buffer
+        } else {
+            &self.color_buffer
+        }
+    }
+
+    /// Returns the depth buffer (row-major, width * height * samples entries, infinity where
+    /// nothing was drawn). The samples of a pixel are next to each other.
+    pub fn depth_buffer(&self) -> &[f32] {
+        &self.depth_buffer
+    }
+
+    /// Returns the color buffer as tightly packed 8-bit RGB rows. What was drawn is resolved first
+    /// (see resolve).
+    pub fn read_pixels(&mut self) -> Vec<u8> {
+        self.resolve();
+        self.color_buffer()
+            .iter()
+            .flat_map(|pixel| {
+                let color = Color::from(*pixel);
+                [color.0, color.1, color.2]
+            })
+            .collect()
+    }
+
+    /// Saves the current frame to an image file. The format is deduced from the extension (.png,
+    /// .ppm, ...). What was drawn is resolved first, like in read_pixels.
+    pub fn save_frame<P: AsRef<Path>>(&mut self, path: P) -> ImageResult<()> {
+        RgbImage::from_raw(self.width, self.height, self.read_pixels())
+            .expect("color buffer doesn't match the renderer size")
+            .save(path)
+    }
+
+    /// Saves the depth buffer as a grayscale image: the nearest drawn pixel is white, the farthest
+    /// one is dark gray and pixels nothing was drawn to are black
+    pub fn save_depth<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        // Only the first sample of each pixel is kept
+        let depths = self.depth_buffer.iter().step_by(self.msaa.samples());
+        let drawn = depths.clone().filter(|depth| depth.is_finite());
+        let min = drawn.clone().fold(f32::INFINITY, |a, b| a.min(*b));
+        let max = drawn.fold(f32::NEG_INFINITY, |a, b| a.max(*b));
+        let range = (max - min).max(f32::EPSILON);
+        let pixels = depths
+            .map(|depth| {
+                if depth.is_finite() {
+                    (255. - (depth - min) / range * 223.) as u8
+                } else {
+                    0
+                }
+            })
+            .collect();
+        GrayImage::from_raw(self.width, self.height, pixels)
+            .expect("depth buffer doesn't match the renderer size")
+            .save(path)
+    }
+
+    /// Resizes the color and depth buffers. Their contents are lost until the next clear.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.allocate_buffers();
+    }
+
     /// Automatically resizes the camera if the screen size changes
     pub fn auto_resize(&mut self, camera: &mut PerspectiveCamera) {
-        for event in self.event_pump.poll_iter() {
-            if let sdl2::event::Event::Window {
-                win_event: sdl2::event::WindowEvent::Resized(width, height),
-                ..
-            } = event
+        if camera.size.x != self.width as f32 || camera.size.y != self.height as f32 {
+            camera.size.x = self.width as f32;
+            camera.size.y = self.height as f32;
+            camera.aspect = self.width as f32 / self.height as f32;
+            camera.generate_projection_matrix();
+        }
+    }
+
+    /// Resolves the samples into the color buffer and applies post-processing. This is done by
+    /// update and read_pixels, but needs to be called before reading color_buffer when MSAA or
+    /// FXAA is used without a presenter.
+    pub fn resolve(&mut self) {
+        if self.resolved {
+            return;
+        }
+        let samples = self.msaa.samples();
+        if samples > 1 {
+            for (pixel, samples) in self
+                .color_buffer
+                .iter_mut()
+                .zip(self.sample_buffer.chunks_exact(samples))
             {
-                self.width = width as u32;
-                self.height = height as u32;
-                camera.size.x = width as f32;
-                camera.size.y = height as f32;
-                camera.aspect = width as f32 / height as f32;
-                camera.generate_projection_matrix();
-                self.empty_buffer = vec![0.; (width * height) as usize].into_boxed_slice();
+                let mut sum = [0; 3];
+                for sample in samples {
+                    let color = Color::from(*sample);
+                    sum[0] += color.0 as usize;
+                    sum[1] += color.1 as usize;
+                    sum[2] += color.2 as usize;
+                }
+                let count = samples.len();
+                *pixel = Color::rgb(
+                    (sum[0] / count) as u8,
+                    (sum[1] / count) as u8,
+                    (sum[2] / count) as u8,
+                )
+                .into();
+            }
+        }
+        if self.fxaa {
+            if self.post_buffer.len() != self.color_buffer.len() {
+                self.post_buffer = vec![0; self.color_buffer.len()].into_boxed_slice();
             }
+            postprocess::fxaa(
+                &self.color_buffer,
+                &mut self.post_buffer,
+                self.width,
+                self.height,
+            );
         }
+        self.resolved = true;
     }
 
-    /// Draws everything, must be called at the end of each loop
+    /// Presents the frame (if there is a presenter), must be called at the end of each loop
     pub fn update(&mut self) {
-        for event in self.event_pump.poll_iter() {
-            if let sdl2::event::Event::Quit { .. } = event {
-                self.running = false;
+        self.resolve();
+        // Taken out while it is used, as the frame is borrowed from the renderer
+        let Some(mut presenter) = self.presenter.take() else {
+            return;
+        };
+        presenter.present(self.color_buffer(), self.width, self.height);
+        for event in presenter.poll_events() {
+            match event {
+                Event::Quit => self.running = false,
+                Event::Resized(width, height) => self.resize(width, height),
             }
         }
-        self.canvas.present();
-        ::std::thread::sleep(std::time::Duration::new(0, 1_000_000_000u32 / 75));
+        self.presenter = Some(presenter);
     }
 
     /// Clears the canvas, must be called at the start of each loop
     pub fn clear(&mut self) {
         self.depth_buffer = self.empty_buffer.clone();
+        self.color_buffer.fill(self.clear_color.into());
+        self.sample_buffer.fill(self.clear_color.into());
+        self.post_buffer.fill(self.clear_color.into());
+        self.resolved = true;
+    }
+
+    /// Draws a barycentric triangle, lit by the world's light
+    pub fn draw_triangle(&mut self, triangle: &Triangle, shader: &dyn Shader, world: &World) {
+        self.resolved = false;
+        let pipeline = self.pipeline(world);
+        let mut tile = Tile {
+            y_start: 0,
+            y_end: self.height as i32,
+            width: self.width,
+            pattern: self.msaa.pattern(),
+            depth_buffer: &mut self.depth_buffer,
+            color_buffer: match self.msaa {
+                Msaa::Off => &mut self.color_buffer,
+                _ => &mut self.sample_buffer,
+            },
+        };
+        tile.rasterize(triangle, shader, &pipeline);
+    }
+
+    /// Rendering settings, for the rasterizer
+    fn pipeline<'a>(&self, world: &'a World) -> Pipeline<'a> {
+        Pipeline {
+            interpolation: self.interpolation,
+            lighting: self.lighting,
+            world,
+        }
+    }
+
+    /// Draws a point (in screen space, z is the depth) as a single pixel
+    pub fn draw_point(&mut self, point: &Vector3, color: Color) {
+        let (x, y) = (point.x.floor(), point.y.floor());
+        if x < 0. || y < 0. || x >= self.width as f32 || y >= self.height as f32 {
+            return;
+        }
+        self.resolved = false;
+        let samples = self.msaa.samples();
+        let index = (y as usize * self.width as usize + x as usize) * samples;
+        let color_buffer = match self.msaa {
+            Msaa::Off => &mut self.color_buffer,
+            _ => &mut self.sample_buffer,
+        };
+        for s in index..index + samples {
+            if point.z < self.depth_buffer[s] {
+                self.depth_buffer[s] = point.z;
+                color_buffer[s] = color.into();
+            }
+        }
+    }
+
+    /// Draws a batch of triangles that share a shader. The screen is split into tiles, triangles
+    /// are binned into the tiles they overlap, and tiles are rasterized in parallel (see
+    /// Renderer.threads). Triangles are drawn in order within each tile, so the result is the same
+    /// as calling draw_triangle for each of them.
+    pub fn draw_triangles(&mut self, triangles: &[Triangle], shader: &dyn Shader, world: &World) {
+        if self.threads <= 1 || triangles.len() < PARALLEL_THRESHOLD {
+            for triangle in triangles {
+                self.draw_triangle(triangle, shader, world);
+            }
+            return;
+        }
 
-        self.canvas.set_draw_color(sdl2::pixels::Color::RGB(
-            self.clear_color.0,
-            self.clear_color.1,
-            self.clear_color.2,
-        ));
-        self.canvas.clear();
-    }
-
-    /// Draws a barycentric triangle
-    pub fn draw_triangle(
-        &mut self,
-        a: &Vector3,
-        b: &Vector3,
-        c: &Vector3,
-        uva: (&Vector2, f32), // contains w for perspective correct textures
-        uvb: (&Vector2, f32),
-        uvc: (&Vector2, f32),
-        shader: &dyn Shader,
-        brightness: f32,
-    ) {
-        // TODO: image buffer
-        // Get bounding box (and then clip to screen bounds)
+        // Tiles are bands of full rows, so each one owns a contiguous slice of the buffers
+        self.resolved = false;
+        let pipeline = &self.pipeline(world);
+        let pattern = self.msaa.pattern();
+        let row_size = self.width as usize * pattern.len();
+        let color_buffer = match self.msaa {
+            Msaa::Off => &mut self.color_buffer,
+            _ => &mut self.sample_buffer,
+        };
+        let mut tiles: Vec<Tile> = color_buffer
+            .chunks_mut(row_size * TILE_HEIGHT as usize)
+            .zip(
+                self.depth_buffer
+                    .chunks_mut(row_size * TILE_HEIGHT as usize),
+            )
+            .enumerate()
+            .map(|(i, (color_buffer, depth_buffer))| Tile {
+                y_start: (i as u32 * TILE_HEIGHT) as i32,
+                y_end: (i as u32 * TILE_HEIGHT) as i32 + (color_buffer.len() / row_size) as i32,
+                width: self.width,
+                pattern,
+                color_buffer,
+                depth_buffer,
+            })
+            .collect();
+
+        // Binning
+        let mut bins: Vec<Vec<&Triangle>> = tiles.iter().map(|_| Vec::new()).collect();
+        for triangle in triangles {
+            let (min_y, max_y) = triangle.rows();
+            let first = (min_y.max(0) as u32 / TILE_HEIGHT) as usize;
+            let last = (max_y.max(0) as u32 / TILE_HEIGHT) as usize;
+            for bin in bins.iter_mut().take(last + 1).skip(first) {
+                bin.push(triangle);
+            }
+        }
+
+        // Rasterization, tiles are interleaved between threads to spread the work evenly
+        let threads = self.threads.min(tiles.len());
+        let mut work: Vec<Vec<(Tile, Vec<&Triangle>)>> = (0..threads).map(|_| Vec::new()).collect();
+        for (i, (tile, bin)) in tiles.drain(..).zip(bins).enumerate() {
+            if !bin.is_empty() {
+                work[i % threads].push((tile, bin));
+            }
+        }
+        std::thread::scope(|scope| {
+            for tiles in work {
+                scope.spawn(move || {
+                    for (mut tile, bin) in tiles {
+                        for triangle in bin {
+                            tile.rasterize(triangle, shader, pipeline);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// Settings that triangles are rasterized with
+struct Pipeline<'a> {
+    interpolation: Interpolation,
+    lighting: Lighting,
+    world: &'a World,
+}
+
+/// Number of rows in a tile
+const TILE_HEIGHT: u32 = 32;
+/// Bits of sub-pixel precision vertices are snapped to
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+/// Largest number of samples per pixel (see Msaa)
+const MAX_SAMPLES: usize = 8;
+/// Batches smaller than this aren't worth spawning threads for
+const PARALLEL_THRESHOLD: usize = 64;
+
+/// A triangle in screen space, ready to be rasterized
+#[derive(Copy, Clone)]
+pub struct Triangle {
+    pub a: Vector3,
+    pub b: Vector3,
+    pub c: Vector3,
+    pub attributes: [Attributes; 3],
+    /// w (in clip space) of each vertex, for perspective correct interpolation
+    pub w: [f32; 3],
+    pub brightness: f32,
+}
+
+impl Triangle {
+    /// Returns the first and last rows covered by the triangle
+    fn rows(&self) -> (i32, i32) {
+        let ys = [self.a.y, self.b.y, self.c.y].map(|y| y as i32);
+        (*ys.iter().min().unwrap(), *ys.iter().max().unwrap())
+    }
+}
+
+/// Rows y_start..y_end of the framebuffer
+struct Tile<'a> {
+    y_start: i32,
+    y_end: i32,
+    width: u32,
+    /// Sample positions (see Msaa), the buffers have one entry per sample
+    pattern: &'static [[i64; 2]],
+    color_buffer: &'a mut [u32],
+    depth_buffer: &'a mut [f32],
+}
+
+impl Tile<'_> {
+    /// Draws the part of a triangle that is inside of the tile.
+    /// Vertices are snapped to a fixed-point sub-pixel grid and pixels are sampled at their
+    /// centers (or at every sample position with MSAA). Samples exactly on an edge follow the
+    /// top-left rule, so samples on an edge shared by two triangles are only drawn once.
+    fn rasterize(&mut self, triangle: &Triangle, shader: &dyn Shader, pipeline: &Pipeline) {
+        let snap = |vertex: &Vector3| {
+            [
+                (vertex.x * SUBPIXEL_ONE as f32).round() as i64,
+                (vertex.y * SUBPIXEL_ONE as f32).round() as i64,
+            ]
+        };
+        // Make sure the vertices are in the order where the inside of the triangle is positive
+        let mut order = [0, 1, 2];
+        let mut points = [snap(&triangle.a), snap(&triangle.b), snap(&triangle.c)];
+        let mut area = EdgeFunction::orient(points[0], points[1], points[2]);
+        if area < 0 {
+            order.swap(1, 2);
+            points.swap(1, 2);
+            area = -area;
+        }
+        if area == 0 {
+            return;
+        }
+        let positions = [triangle.a, triangle.b, triangle.c];
+        let [a, b, c] = order.map(|i| positions[i]);
+        let attributes = order.map(|i| triangle.attributes[i]);
+        let w = order.map(|i| triangle.w[i]);
+        let alpha_mode = shader.alpha_mode();
+
+        // Get bounding box (and then clip to the tile bounds). With MSAA, samples of the pixels
+        // around the box can be inside of the triangle too.
+        let half = SUBPIXEL_ONE / 2;
+        let margin = if self.pattern.len() > 1 { 1 } else { 0 };
+        let pixel = |coordinate: i64| ((coordinate - half) >> SUBPIXEL_BITS) as i32;
+        let min_x = 0.max(pixel(points.iter().map(|p| p[0]).min().unwrap()) - margin);
         let max_x =
-            (self.width as i32).min(*[a.x, b.x, c.x].map(|y| y as i32).iter().max().unwrap());
+            (self.width as i32 - 1).min(pixel(points.iter().map(|p| p[0]).max().unwrap()) + margin);
+        let min_y = self
+            .y_start
+            .max(pixel(points.iter().map(|p| p[1]).min().unwrap()) - margin);
         let max_y =
-            (self.height as i32).min(*[a.y, b.y, c.y].map(|y| y as i32).iter().max().unwrap());
-        let min_x = 0.max(*[a.x, b.x, c.x].map(|x| x as i32).iter().min().unwrap());
-        let min_y = 0.max(*[a.y, b.y, c.y].map(|y| y as i32).iter().min().unwrap());
-        // Get the barycentric coordinates at the top left and when x or y increments
-        let top_left = Renderer::get_barycentric_coords(
-            a.x,
-            a.y,
-            b.x,
-            b.y,
-            c.x,
-            c.y,
-            min_x as f32,
-            min_y as f32,
-        );
-        let delta_y = Renderer::get_barycentric_coords(
-            a.x,
-            a.y,
-            b.x,
-            b.y,
-            c.x,
-            c.y,
-            min_x as f32,
-            (min_y + 1) as f32,
-        ) - top_left;
-        let delta_x = Renderer::get_barycentric_coords(
-            a.x,
-            a.y,
-            b.x,
-            b.y,
-            c.x,
-            c.y,
-            (min_x + 1) as f32,
-            min_y as f32,
-        ) - top_left;
+            (self.y_end - 1).min(pixel(points.iter().map(|p| p[1]).max().unwrap()) + margin);
+        if min_x > max_x || min_y > max_y {
+            return;
+        }
+
+        // Edge functions at the center of the top left pixel. The one for the edge opposite to a
+        // vertex is the barycentric weight of that vertex (times the area)
+        let origin = [
+            ((min_x as i64) << SUBPIXEL_BITS) + half,
+            ((min_y as i64) << SUBPIXEL_BITS) + half,
+        ];
+        let edges = [
+            EdgeFunction::new(points[1], points[2], origin),
+            EdgeFunction::new(points[2], points[0], origin),
+            EdgeFunction::new(points[0], points[1], origin),
+        ];
+        let inverse_area = 1. / area as f32;
+        let inside = |values: &[i64; 3]| (0..3).all(|i| values[i] + edges[i].bias >= 0);
+        let barycentric = |values: &[i64; 3]| {
+            Vector3::new(
+                values[0] as f32 * inverse_area,
+                values[1] as f32 * inverse_area,
+                values[2] as f32 * inverse_area,
+            )
+        };
+        // How much the edge functions change between the pixel center and each sample
+        let samples = self.pattern.len();
+        let mut sample_offsets = [[0; 3]; MAX_SAMPLES];
+        for (offsets, sample) in sample_offsets.iter_mut().zip(self.pattern) {
+            *offsets = edges.map(|edge| edge.offset(sample));
+        }
+
         // Drawing
-        let mut coords_row = top_left;
+        let inverse_w = Vector3::new(1. / w[0], 1. / w[1], 1. / w[2]);
+        let mut values_row = edges.map(|edge| edge.value);
         for y in min_y..(max_y + 1) {
-            // Barycentric coordinates for the left of the row.
-            let mut coords = coords_row + delta_y;
-            coords_row = coords;
+            let mut values = values_row;
+            for i in 0..3 {
+                values_row[i] += edges[i].step_y;
+            }
             for x in min_x..(max_x + 1) {
-                // Barycentric coordinates
-                coords = coords + delta_x;
-                if coords.x >= 0. && coords.y >= 0. && coords.z >= 0. {
-                    // Depth
-                    let depth_index = self.width as usize * y as usize + x as usize;
-                    let depth_entry = self.depth_buffer[depth_index];
-                    let depth = coords.x * a.z + coords.y * b.z + coords.z * c.z;
-                    if depth > 0. && (depth_entry > depth || depth_entry == 0.) {
-                        // Write to screen / depth buffer
-                        self.depth_buffer[depth_index] = depth;
-                        self.draw_pixel(
-                            x,
-                            y,
-                            &(shader.fragment(&coords, uva, uvb, uvc) * brightness),
+                let center = values;
+                for i in 0..3 {
+                    values[i] += edges[i].step_x;
+                }
+                // Coverage and depth test of every sample
+                let index =
+                    (self.width as usize * (y - self.y_start) as usize + x as usize) * samples;
+                let mut covered = 0u32;
+                let mut first_covered = None;
+                let mut depths = [0.; MAX_SAMPLES];
+                for (s, offsets) in sample_offsets.iter().take(samples).enumerate() {
+                    let sample = [0, 1, 2].map(|i| center[i] + offsets[i]);
+                    if inside(&sample) {
+                        let coords = barycentric(&sample);
+                        let depth = coords.x * a.z + coords.y * b.z + coords.z * c.z;
+                        if depth < self.depth_buffer[index + s] {
+                            depths[s] = depth;
+                            covered |= 1 << s;
+                            first_covered.get_or_insert(sample);
+                        }
+                    }
+                }
+                let Some(first_covered) = first_covered else {
+                    continue;
+                };
+
+                // Shading, once per pixel: at its center if it is inside of the triangle, or else
+                // at a covered sample (so that attributes aren't extrapolated)
+                let shaded = if inside(&center) {
+                    center
+                } else {
+                    first_covered
+                };
+                let correct = |coords: Vector3| match pipeline.interpolation {
+                    Interpolation::PerspectiveCorrect => {
+                        // Interpolate 1/w, then weight each vertex by (1/w) / (1/w at the pixel)
+                        let coords = Vector3::new(
+                            coords.x * inverse_w.x,
+                            coords.y * inverse_w.y,
+                            coords.z * inverse_w.z,
                         );
+                        coords * (1. / (coords.x + coords.y + coords.z))
+                    }
+                    Interpolation::Affine => coords,
+                };
+                let attribute_coords = correct(barycentric(&shaded));
+                // Coordinates at the next pixels, even if they are outside of the triangle
+                let next = |step: fn(&EdgeFunction) -> i64| {
+                    correct(barycentric(&[0, 1, 2].map(|i| shaded[i] + step(&edges[i]))))
+                        - attribute_coords
+                };
+                let derivatives = Derivatives {
+                    dx: next(|edge| edge.step_x),
+                    dy: next(|edge| edge.step_y),
+                };
+                let color = shader.fragment(&attribute_coords, &derivatives, &attributes);
+                // Alpha test, before anything is written
+                if let AlphaMode::Mask(cutoff) = alpha_mode {
+                    if (color.3 as f32) < cutoff * 255. {
+                        continue;
+                    }
+                }
+                let color = match pipeline.lighting {
+                    Lighting::Flat => color * triangle.brightness,
+                    Lighting::Gouraud => {
+                        let [a, b, c] = attributes.map(|vertex| vertex.brightness);
+                        color
+                            * (attribute_coords.x * a
+                                + attribute_coords.y * b
+                                + attribute_coords.z * c)
+                    }
+                    lighting => shader.light_fragment(
+                        color,
+                        &Attributes::interpolate(&attribute_coords, &attributes),
+                        pipeline.world,
+                        lighting,
+                    ),
+                };
+                for (s, depth) in depths.iter().take(samples).enumerate() {
+                    if covered & (1 << s) == 0 {
+                        continue;
+                    }
+                    match alpha_mode {
+                        AlphaMode::Blend(blend) => {
+                            let behind = Color::from(self.color_buffer[index + s]);
+                            self.color_buffer[index + s] = blend.apply(color, behind).into();
+                        }
+                        _ => {
+                            self.depth_buffer[index + s] = *depth;
+                            self.color_buffer[index + s] = color.into();
+                        }
                     }
                 }
             }
         }
     }
+}
 
-    fn get_barycentric_coords(
-        a_x: f32,
-        a_y: f32,
-        b_x: f32,
-        b_y: f32,
-        c_x: f32,
-        c_y: f32,
-        p_x: f32,
-        p_y: f32,
-    ) -> Vector3 {
-        let dca = math::Vector2::new(c_x - a_x, c_y - a_y);
-        let dpa = math::Vector2::new(p_x - a_x, p_y - a_y);
-        let dba = math::Vector2::new(b_x - a_x, b_y - a_y);
-        let deti = 1. / (dba.x * dca.y - dca.x * dba.y);
-
-        let v = (dpa.x * dca.y - dpa.y * dca.x) * deti;
-        let w = (dpa.y * dba.x - dpa.x * dba.y) * deti;
+/// Edge function of a triangle, in fixed point (see SUBPIXEL_BITS)
+#[derive(Copy, Clone)]
+struct EdgeFunction {
+    /// Value at the origin the function was set up with
+    value: i64,
+    /// Added when moving one pixel to the right
+    step_x: i64,
+    /// Added when moving one pixel down
+    step_y: i64,
+    /// -1 for edges that aren't top or left edges, so that points on them are outside
+    bias: i64,
+}
 
-        Vector3 {
-            x: 1. - v - w,
-            y: v,
-            z: w,
+impl EdgeFunction {
+    /// Sets up the edge function of the edge going from `from` to `to`, evaluated at `origin`
+    fn new(from: [i64; 2], to: [i64; 2], origin: [i64; 2]) -> EdgeFunction {
+        let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+        // With y pointing down and the inside of the triangle positive, top edges are horizontal
+        // and go right, left edges go up
+        let top_left = dy < 0 || (dy == 0 && dx > 0);
+        EdgeFunction {
+            value: EdgeFunction::orient(from, to, origin),
+            step_x: -dy * SUBPIXEL_ONE,
+            step_y: dx * SUBPIXEL_ONE,
+            bias: if top_left { 0 } else { -1 },
         }
     }
 
-    fn draw_pixel(&mut self, x: i32, y: i32, color: &Color) {
-        self.canvas
-            .set_draw_color(sdl2::pixels::Color::RGB(color.0, color.1, color.2));
-        self.canvas
-            .draw_point(sdl2::rect::Point::new(x, y))
-            .expect(":(");
+    /// Returns how much the function changes when moving by `offset` (in sub-pixel units)
+    fn offset(&self, offset: &[i64; 2]) -> i64 {
+        (self.step_x * offset[0] + self.step_y * offset[1]) / SUBPIXEL_ONE
+    }
+
+    /// Twice the signed area of the triangle a, b, c
+    fn orient(a: [i64; 2], b: [i64; 2], c: [i64; 2]) -> i64 {
+        (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+    }
+}
+
+impl From<Color> for u32 {
+    /// Packs a color as 0x00RRGGBB, dropping its alpha
+    fn from(color: Color) -> u32 {
+        (color.0 as u32) << 16 | (color.1 as u32) << 8 | color.2 as u32
+    }
+}
+
+impl From<u32> for Color {
+    /// Unpacks a color stored as 0x00RRGGBB, as an opaque color
+    fn from(pixel: u32) -> Color {
+        Color::rgb((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+    }
+}
+
+impl std::ops::Add<Color> for Color {
+    type Output = Color;
+
+    /// Adds the red, green and blue of two colors (adding light), keeping the alpha of the first
+    fn add(self, rhs: Color) -> Self::Output {
+        Color(
+            self.0.saturating_add(rhs.0),
+            self.1.saturating_add(rhs.1),
+            self.2.saturating_add(rhs.2),
+            self.3,
+        )
+    }
+}
+
+impl std::ops::Mul<Color> for Color {
+    type Output = Color;
+
+    /// Multiplies every channel of two colors (tinting), as if they were from 0 to 1
+    fn mul(self, rhs: Color) -> Self::Output {
+        let mix = |a: u8, b: u8| ((a as u32 * b as u32 + 127) / 255) as u8;
+        Color(
+            mix(self.0, rhs.0),
+            mix(self.1, rhs.1),
+            mix(self.2, rhs.2),
+            mix(self.3, rhs.3),
+        )
     }
 }
 
 impl std::ops::Mul<f32> for Color {
     type Output = Color;
 
+    /// Scales the red, green and blue of a color (lighting it), keeping its alpha
     fn mul(self, rhs: f32) -> Self::Output {
         Color(
             (self.0 as f32 * rhs) as u8,
             (self.1 as f32 * rhs) as u8,
             (self.2 as f32 * rhs) as u8,
+            self.3,
         )
     }
 }
@@ -0,0 +1,13 @@
+pub mod camera;
+pub mod clipping;
+pub mod exporter;
+pub mod graphics;
+pub mod loader;
+pub mod material;
+pub mod math;
+pub mod mesh;
+pub mod postprocess;
+pub mod presenter;
+pub mod shaders;
+pub mod texture;
+pub mod world;
@@ -1,74 +1,1392 @@
-use std::fs;
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
-    math::{Vector2, Vector3},
-    mesh::{Face, Mesh},
-    shaders,
+    graphics::{AlphaMode, Blend, Color},
+    material::Material,
+    math::{Vector2, Vector3, Vector4},
+    mesh::{Face, Group, Mesh, Primitive, DEFAULT_CREASE_ANGLE},
+    shaders::VertexColorShader,
+    texture::{ColorSpace, Filter, Mipmaps, Sampler, Texture, Wrap},
 };
 
-/// Loads an .obj file to a Mesh
-pub fn load(file_path: &str) -> Mesh {
-    let contents = fs::read_to_string(file_path).expect("Cannot open .obj");
+/// Error returned when a model or texture can't be loaded
+#[derive(Debug)]
+pub struct LoadError {
+    pub file: PathBuf,
+    /// Line (starting at 1) the error is on, for text formats
+    pub line: Option<usize>,
+    pub kind: LoadErrorKind,
+}
+
+#[derive(Debug)]
+pub enum LoadErrorKind {
+    /// The file can't be read
+    Io(std::io::Error),
+    /// A number can't be parsed
+    MalformedFloat(String),
+    /// An index can't be parsed or refers to something that doesn't exist
+    BadIndex(String),
+    /// A statement has less values than it needs (or a glTF attribute has a different count than
+    /// the positions)
+    MissingValues { expected: usize, found: usize },
+    /// A texture file can't be opened
+    MissingTexture(std::io::Error),
+    /// A texture file can't be decoded
+    InvalidTexture(image::ImageError),
+    /// A glTF file (or one of its buffers or images) is invalid
+    Gltf(gltf::Error),
+    /// A binary file ends before all of its data
+    UnexpectedEnd,
+    /// A file uses something that isn't supported (or has a malformed header)
+    Unsupported(String),
+}
+
+impl LoadError {
+    pub fn new(file: &Path, line: Option<usize>, kind: LoadErrorKind) -> LoadError {
+        LoadError {
+            file: file.to_path_buf(),
+            line,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        match &self.kind {
+            LoadErrorKind::Io(error) => write!(f, ": can't read file ({})", error),
+            LoadErrorKind::MalformedFloat(value) => write!(f, ": malformed number '{}'", value),
+            LoadErrorKind::BadIndex(index) => write!(f, ": bad index '{}'", index),
+            LoadErrorKind::MissingValues { expected, found } => {
+                write!(f, ": expected {} values, found {}", expected, found)
+            }
+            LoadErrorKind::MissingTexture(error) => write!(f, ": can't open texture ({})", error),
+            LoadErrorKind::InvalidTexture(error) => write!(f, ": can't decode texture ({})", error),
+            LoadErrorKind::Gltf(error) => write!(f, ": invalid glTF ({})", error),
+            LoadErrorKind::UnexpectedEnd => write!(f, ": unexpected end of file"),
+            LoadErrorKind::Unsupported(what) => write!(f, ": unsupported {}", what),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            LoadErrorKind::Io(error) | LoadErrorKind::MissingTexture(error) => Some(error),
+            LoadErrorKind::InvalidTexture(error) => Some(error),
+            LoadErrorKind::Gltf(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// One corner of a face in an .obj file: indices of its vertex, UV and normal
+#[derive(Copy, Clone)]
+struct Corner {
+    vertex: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// Loads an .obj file to a Mesh.
+/// Polygons are triangulated, and faces without UVs share a (0, 0) UV added at the end of the
+/// mesh's UVs. Materials are read from the .mtl files the model refers to, and each run of faces
+/// using the same material becomes a primitive with a shader made from that material.
+/// Corners without a `vn` get a normal generated from their smoothing group (see
+/// Mesh::generate_normals).
+pub fn load(file_path: &str) -> Result<Mesh, LoadError> {
+    let path = Path::new(file_path);
+    let contents = fs::read_to_string(path)
+        .map_err(|error| LoadError::new(path, None, LoadErrorKind::Io(error)))?;
     let mut vertices: Vec<Vector3> = Vec::new();
     let mut uvs: Vec<Vector2> = Vec::new();
-    let mut faces: Vec<Face> = Vec::new();
-
-    for line in contents.split('\n') {
-        if line.len() > 2 {
-            let symbol = &line[..2];
-            match symbol {
-                "v " => {
-                    let split = &line[2..].split(' ').collect::<Vec<_>>();
-                    let vector = Vector3::new(
-                        split[0].parse().unwrap(),
-                        split[1].parse().unwrap(),
-                        split[2].parse().unwrap(),
-                    );
-                    vertices.push(vector);
-
-                    println!("v {} {} {}", split[0], split[1], split[2]);
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut triangles: Vec<([Corner; 3], Option<u32>)> = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+    // None until the first s statement
+    let mut smoothing_group = None;
+    let mut materials: Vec<Material> = Vec::new();
+    // Ranges of faces that use the same material
+    let mut primitives: Vec<(Range<usize>, Option<usize>)> = vec![(0..0, None)];
+
+    for (line_number, line) in logical_lines(&contents) {
+        let error = |kind| LoadError::new(path, Some(line_number), kind);
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        let expect = |expected: usize| {
+            if arguments.len() < expected {
+                Err(error(LoadErrorKind::MissingValues {
+                    expected,
+                    found: arguments.len(),
+                }))
+            } else {
+                Ok(())
+            }
+        };
+        let float = |i: usize| {
+            arguments[i]
+                .parse::<f32>()
+                .map_err(|_| error(LoadErrorKind::MalformedFloat(arguments[i].to_string())))
+        };
+        match keyword {
+            "v" => {
+                expect(3)?;
+                vertices.push(Vector3::new(float(0)?, float(1)?, float(2)?));
+            }
+            "vt" => {
+                expect(1)?;
+                // TODO: inverts uvy, bad idea?
+                let v = if arguments.len() > 1 { float(1)? } else { 0. };
+                uvs.push(Vector2::new(float(0)?, 1. - v));
+            }
+            "vn" => {
+                expect(3)?;
+                normals.push(Vector3::new(float(0)?, float(1)?, float(2)?));
+            }
+            "f" => {
+                expect(3)?;
+                let corners = arguments
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(corner, vertices.len(), uvs.len(), normals.len())
+                            .ok_or_else(|| error(LoadErrorKind::BadIndex(corner.to_string())))
+                    })
+                    .collect::<Result<Vec<Corner>, LoadError>>()?;
+                let positions: Vec<Vector3> = corners
+                    .iter()
+                    .map(|corner| vertices[corner.vertex])
+                    .collect();
+                for [a, b, c] in triangulate(&positions) {
+                    triangles.push(([corners[a], corners[b], corners[c]], smoothing_group));
                 }
-                "vt" => {
-                    let split = &line[3..].split(' ').collect::<Vec<_>>();
-                    // TODO: inverts uvy, bad idea?
-                    let vector = Vector2::new(
-                        split[0].parse().unwrap(),
-                        1. - split[1].parse::<f32>().unwrap(),
-                    );
-                    uvs.push(vector);
-
-                    println!("vt {} {}", split[0], split[1]);
+            }
+            "o" | "g" => {
+                if let Some(group) = groups.last_mut() {
+                    group.faces.end = triangles.len();
                 }
-                "vn" => {
-                    // TODO: vector normals
+                groups.push(Group {
+                    name: arguments.join(" "),
+                    faces: triangles.len()..triangles.len(),
+                });
+            }
+            "mtllib" => {
+                let directory = path.parent().unwrap_or(Path::new(""));
+                for library in &arguments {
+                    materials.extend(load_mtl(&directory.join(library).to_string_lossy())?);
                 }
-                "f " => {
-                    let split = &line[2..].split(' ').collect::<Vec<_>>();
-                    let mut face_vertices: Vec<usize> = Vec::new();
-                    let mut face_uvs: Vec<usize> = Vec::new();
-                    for vertex in split {
-                        let vertex_split = &vertex[0..].split('/').collect::<Vec<_>>();
-                        face_vertices.push(vertex_split[0].parse::<usize>().unwrap() - 1);
-                        face_uvs.push(vertex_split[1].parse::<usize>().unwrap() - 1);
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                let material = materials.iter().position(|material| material.name == name);
+                // Faces after this go in a new primitive, unless the current one doesn't have
+                // any faces yet
+                let (faces, current) = primitives.last_mut().unwrap();
+                if faces.start == triangles.len() {
+                    *current = material;
+                } else {
+                    faces.end = triangles.len();
+                    primitives.push((triangles.len()..triangles.len(), material));
+                }
+            }
+            "s" => {
+                smoothing_group = match arguments.first() {
+                    Some(&"off") | None => Some(0),
+                    Some(group) => Some(
+                        group
+                            .parse()
+                            .map_err(|_| error(LoadErrorKind::BadIndex(group.to_string())))?,
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(group) = groups.last_mut() {
+        group.faces.end = triangles.len();
+    }
+    groups.retain(|group| !group.faces.is_empty());
+    primitives.last_mut().unwrap().0.end = triangles.len();
+    primitives.retain(|(faces, _)| !faces.is_empty());
+
+    // Placeholder for faces without UVs
+    let default_uv = uvs.len();
+    if triangles
+        .iter()
+        .any(|(corners, _)| corners.iter().any(|corner| corner.uv.is_none()))
+    {
+        uvs.push(Vector2::new(0., 0.));
+    }
+
+    let faces = triangles
+        .iter()
+        .map(|([a, b, c], smoothing_group)| {
+            let mut face = Face::new(
+                a.vertex,
+                b.vertex,
+                c.vertex,
+                a.uv.unwrap_or(default_uv),
+                b.uv.unwrap_or(default_uv),
+                c.uv.unwrap_or(default_uv),
+            );
+            face.na = a.normal;
+            face.nb = b.normal;
+            face.nc = c.normal;
+            face.smoothing_group = *smoothing_group;
+            face.compute_normal(&vertices);
+            face
+        })
+        .collect();
+
+    // Textures are decoded once, for the materials that are used
+    for material in primitives.iter().filter_map(|(_, material)| *material) {
+        materials[material].load_textures()?;
+    }
+    let mut mesh = Mesh::new(vertices, faces, uvs);
+    mesh.primitives = primitives
+        .into_iter()
+        .map(|(faces, material)| {
+            Ok(match material {
+                Some(material) => {
+                    Primitive::with_material(faces, material, materials[material].shader()?)
+                }
+                None => Primitive::new(faces),
+            })
+        })
+        .collect::<Result<Vec<Primitive>, LoadError>>()?;
+    mesh.normals = normals;
+    mesh.generate_normals(DEFAULT_CREASE_ANGLE);
+    mesh.groups = groups;
+    mesh.materials = materials;
+    Ok(mesh)
+}
+
+/// Loads the materials of an .mtl file. Texture paths are relative to the file.
+pub fn load_mtl(file_path: &str) -> Result<Vec<Material>, LoadError> {
+    let path = Path::new(file_path);
+    let contents = fs::read_to_string(path)
+        .map_err(|error| LoadError::new(path, None, LoadErrorKind::Io(error)))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut materials: Vec<Material> = Vec::new();
+
+    for (line_number, line) in logical_lines(&contents) {
+        let error = |kind| LoadError::new(path, Some(line_number), kind);
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments: Vec<&str> = tokens.collect();
+        if arguments.is_empty() {
+            return Err(error(LoadErrorKind::MissingValues {
+                expected: 1,
+                found: 0,
+            }));
+        }
+        let float = |i: usize| {
+            arguments[i]
+                .parse::<f32>()
+                .map_err(|_| error(LoadErrorKind::MalformedFloat(arguments[i].to_string())))
+        };
+        // Colors have either one value for all channels or one per channel
+        let color = || {
+            let r = float(0)?;
+            let (g, b) = if arguments.len() >= 3 {
+                (float(1)?, float(2)?)
+            } else {
+                (r, r)
+            };
+            Ok(color_from_floats(r, g, b, 1.))
+        };
+        // Texture options (-s 1 1 1, -bm 0.5...) come before the file name
+        let map = || Some(directory.join(arguments.last().unwrap()));
+
+        if keyword == "newmtl" {
+            materials.push(Material::new(&arguments.join(" ")));
+            continue;
+        }
+        // Statements before the first newmtl don't belong to any material
+        let Some(material) = materials.last_mut() else {
+            continue;
+        };
+        match keyword {
+            // Spectral and CIE XYZ colors aren't supported
+            "Ka" | "Kd" | "Ks" if ["spectral", "xyz"].contains(&arguments[0]) => {}
+            "Ka" => material.ambient = color()?,
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = float(0)?,
+            "d" => material.opacity = float(0)?,
+            "Tr" => material.opacity = 1. - float(0)?,
+            "map_Kd" => material.diffuse_map = map(),
+            "map_Bump" | "map_bump" | "bump" => material.bump_map = map(),
+            "map_d" => material.alpha_map = map(),
+            _ => {}
+        }
+    }
+    // Materials that aren't fully opaque are blended, and alpha maps cut materials out where they
+    // are below one half
+    for material in &mut materials {
+        material.alpha_mode = if material.opacity < 1. {
+            AlphaMode::Blend(Blend::ALPHA)
+        } else if material.alpha_map.is_some() {
+            AlphaMode::Mask(0.5)
+        } else {
+            AlphaMode::Opaque
+        };
+    }
+    Ok(materials)
+}
+
+/// Converts a color with channels from 0 to 1
+fn color_from_floats(r: f32, g: f32, b: f32, a: f32) -> Color {
+    let channel = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+    Color(channel(r), channel(g), channel(b), channel(a))
+}
+
+/// Splits a file into lines without comments, joining lines that end with a backslash with the
+/// next one. Lines are returned with the number of the line they start on.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 1;
+    for (i, line) in contents.lines().enumerate() {
+        if current.is_empty() {
+            start = i + 1;
+        }
+        let line = line.split('#').next().unwrap();
+        if let Some(line) = line.trim_end().strip_suffix('\\') {
+            current.push_str(line);
+            current.push(' ');
+        } else {
+            current.push_str(line);
+            lines.push((start, std::mem::take(&mut current)));
+        }
+    }
+    if !current.is_empty() {
+        lines.push((start, current));
+    }
+    lines
+}
+
+/// Parses a face corner (`v`, `v/vt`, `v//vn` or `v/vt/vn`). Indices start at 1, negative ones
+/// are relative to the end of what has been read so far.
+/// Returns None if an index is malformed or refers to something that hasn't been read.
+fn parse_corner(corner: &str, vertices: usize, uvs: usize, normals: usize) -> Option<Corner> {
+    let resolve = |index: &str, count: usize| {
+        let index: i64 = index.parse().ok()?;
+        let index = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        (0..count as i64).contains(&index).then_some(index as usize)
+    };
+    let mut indices = corner.split('/');
+    let vertex = resolve(indices.next()?, vertices)?;
+    let uv = match indices.next().filter(|index| !index.is_empty()) {
+        Some(index) => Some(resolve(index, uvs)?),
+        None => None,
+    };
+    let normal = match indices.next().filter(|index| !index.is_empty()) {
+        Some(index) => Some(resolve(index, normals)?),
+        None => None,
+    };
+    Some(Corner { vertex, uv, normal })
+}
+
+/// Splits a polygon into triangles by ear clipping, which also works for concave polygons.
+/// Returns the indices of the corners of each triangle, in the winding order of the polygon.
+fn triangulate(polygon: &[Vector3]) -> Vec<[usize; 3]> {
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+    if polygon.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Project the polygon onto the plane it's (mostly) in, using its normal (Newell's method)
+    let mut normal = Vector3::new(0., 0., 0.);
+    for (i, p) in polygon.iter().enumerate() {
+        let q = polygon[(i + 1) % polygon.len()];
+        normal.x += (p.y - q.y) * (p.z + q.z);
+        normal.y += (p.z - q.z) * (p.x + q.x);
+        normal.z += (p.x - q.x) * (p.y + q.y);
+    }
+    let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+    let points: Vec<Vector2> = polygon
+        .iter()
+        .map(|p| {
+            if x >= y && x >= z {
+                Vector2::new(p.y, p.z)
+            } else if y >= z {
+                Vector2::new(p.z, p.x)
+            } else {
+                Vector2::new(p.x, p.y)
+            }
+        })
+        .collect();
+    // Positive when a, b, c turn the same way as the polygon
+    let orientation = if x >= y && x >= z {
+        normal.x.signum()
+    } else if y >= z {
+        normal.y.signum()
+    } else {
+        normal.z.signum()
+    };
+    let turn = |a: usize, b: usize, c: usize| {
+        let (ab, ac) = (points[b] - points[a], points[c] - points[a]);
+        (ab.x * ac.y - ab.y * ac.x) * orientation
+    };
+
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let corners = |i: usize| {
+            (
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            )
+        };
+        // An ear is a convex corner with no other corner inside of it
+        let ear = (0..count).find(|i| {
+            let (previous, current, next) = corners(*i);
+            turn(previous, current, next) > 0.
+                && !remaining.iter().any(|other| {
+                    ![previous, current, next].contains(other)
+                        && turn(previous, current, *other) >= 0.
+                        && turn(current, next, *other) >= 0.
+                        && turn(next, previous, *other) >= 0.
+                })
+        });
+        // Degenerate polygon, give up and use a fan for the rest
+        let Some(ear) = ear else {
+            break;
+        };
+        let (previous, current, next) = corners(ear);
+        triangles.push([previous, current, next]);
+        remaining.remove(ear);
+    }
+    for i in 1..(remaining.len() - 1) {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+//
+// glTF
+//
+/// Column-major 4x4 matrix, as used by glTF
+type GltfMatrix = [[f32; 4]; 4];
+
+const IDENTITY: GltfMatrix = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+/// Loads the meshes in the default scene of a glTF 2.0 file (.gltf or .glb, with embedded or
+/// external buffers). Every node with a mesh becomes a Mesh with a primitive per glTF primitive.
+/// The scale of the node (and of its parents) is applied to the vertices, while their rotation
+/// and translation go in the mesh's transformation.
+pub fn load_gltf(file_path: &str) -> Result<Vec<Mesh>, LoadError> {
+    let path = Path::new(file_path);
+    let (document, buffers, images) = gltf::import(path)
+        .map_err(|error| LoadError::new(path, None, LoadErrorKind::Gltf(error)))?;
+    // Meshes share the materials, and materials share the decoded images
+    let mut textures = HashMap::new();
+    let materials = document
+        .materials()
+        .map(|material| gltf_material(path, &material, &images, &mut textures))
+        .collect::<Result<Vec<Material>, LoadError>>()?;
+
+    let mut meshes = Vec::new();
+    let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    else {
+        return Ok(meshes);
+    };
+    // Depth-first, with the transformation of the parent of each node
+    let mut nodes: Vec<(gltf::Node, GltfMatrix)> =
+        scene.nodes().map(|node| (node, IDENTITY)).collect();
+    nodes.reverse();
+    while let Some((node, parent)) = nodes.pop() {
+        let matrix = multiply(&parent, &node.transform().matrix());
+        let children: Vec<_> = node.children().map(|child| (child, matrix)).collect();
+        nodes.extend(children.into_iter().rev());
+        if let Some(mesh) = node.mesh() {
+            let name = node.name().or(mesh.name()).unwrap_or_default();
+            meshes.push(gltf_mesh(path, &mesh, name, matrix, &buffers, &materials)?);
+        }
+    }
+    Ok(meshes)
+}
+
+fn multiply(a: &GltfMatrix, b: &GltfMatrix) -> GltfMatrix {
+    let mut result = [[0.; 4]; 4];
+    for (column, result_column) in result.iter_mut().enumerate() {
+        for (row, value) in result_column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    result
+}
+
+fn gltf_mesh(
+    path: &Path,
+    mesh: &gltf::Mesh,
+    name: &str,
+    matrix: GltfMatrix,
+    buffers: &[gltf::buffer::Data],
+    materials: &[Material],
+) -> Result<Mesh, LoadError> {
+    let (translation, rotation, scale) = gltf::scene::Transform::Matrix { matrix }.decomposed();
+    let mut vertices = Vec::new();
+    let mut uvs = Vec::new();
+    // Normals and tangents are per vertex, and zero for primitives without them
+    let mut normals = Vec::new();
+    let mut tangents = Vec::new();
+    let mut faces = Vec::new();
+    let mut primitives = Vec::new();
+    let (mut has_normals, mut has_tangents) = (false, false);
+
+    for primitive in mesh.primitives() {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            continue;
+        };
+        let offset = vertices.len();
+        vertices.extend(
+            positions.map(|p| Vector3::new(p[0] * scale[0], p[1] * scale[1], p[2] * scale[2])),
+        );
+        let count = vertices.len() - offset;
+        // Every attribute needs a value per position
+        let check = |found: usize| {
+            if found == count {
+                return Ok(());
+            }
+            let kind = LoadErrorKind::MissingValues {
+                expected: count,
+                found,
+            };
+            Err(LoadError::new(path, None, kind))
+        };
+
+        // glTF UVs start at the top left, like ours
+        match reader.read_tex_coords(0) {
+            Some(coords) => {
+                let coords: Vec<_> = coords.into_f32().collect();
+                check(coords.len())?;
+                uvs.extend(coords.iter().map(|uv| Vector2::new(uv[0], uv[1])));
+            }
+            None => uvs.resize(vertices.len(), Vector2::new(0., 0.)),
+        }
+        let primitive_normals = match reader.read_normals() {
+            // Scaling a surface scales its normals by the inverse
+            Some(read) => {
+                let read: Vec<_> = read.collect();
+                check(read.len())?;
+                normals.extend(read.iter().map(|n| {
+                    Vector3::new(n[0] / scale[0], n[1] / scale[1], n[2] / scale[2]).normalised()
+                }));
+                true
+            }
+            None => {
+                normals.resize(vertices.len(), Vector3::new(0., 0., 0.));
+                false
+            }
+        };
+        has_normals |= primitive_normals;
+        match reader.read_tangents() {
+            Some(read) => {
+                let read: Vec<_> = read.collect();
+                check(read.len())?;
+                has_tangents = true;
+                tangents.extend(read.iter().map(|t| {
+                    let direction = Vector3::new(t[0] * scale[0], t[1] * scale[1], t[2] * scale[2])
+                        .normalised();
+                    Vector4::new(direction.x, direction.y, direction.z, t[3])
+                }))
+            }
+            None => tangents.resize(vertices.len(), Vector4::new(0., 0., 0., 0.)),
+        }
+
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| offset + i as usize).collect(),
+            None => (offset..offset + count).collect(),
+        };
+        let triangles: Vec<[usize; 3]> = match primitive.mode() {
+            gltf::mesh::Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|t| [t[0], t[1], t[2]])
+                .collect(),
+            // Every other triangle of a strip is flipped to keep the winding order
+            gltf::mesh::Mode::TriangleStrip => (0..indices.len().saturating_sub(2))
+                .map(|i| {
+                    if i % 2 == 0 {
+                        [indices[i], indices[i + 1], indices[i + 2]]
+                    } else {
+                        [indices[i + 1], indices[i], indices[i + 2]]
                     }
+                })
+                .collect(),
+            gltf::mesh::Mode::TriangleFan => (1..indices.len().saturating_sub(1))
+                .map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+            // Points and lines
+            _ => Vec::new(),
+        };
+        // Skip indices that point outside of the primitive's vertices
+        let valid = offset..offset + count;
 
-                    let mut face = Face::new(
-                        face_vertices[0],
-                        face_vertices[1],
-                        face_vertices[2],
-                        face_uvs[0],
-                        face_uvs[1],
-                        face_uvs[2],
-                    );
-                    face.compute_normal(&vertices);
-                    faces.push(face);
-
-                    println!("f {} {} {}", split[0], split[1], split[2]);
+        let start = faces.len();
+        for [a, b, c] in triangles {
+            if !(valid.contains(&a) && valid.contains(&b) && valid.contains(&c)) {
+                continue;
+            }
+            let mut face = Face::new(a, b, c, a, b, c);
+            if primitive_normals {
+                (face.na, face.nb, face.nc) = (Some(a), Some(b), Some(c));
+            }
+            face.compute_normal(&vertices);
+            faces.push(face);
+        }
+        let faces = start..faces.len();
+
+        primitives.push(match primitive.material().index() {
+            Some(material) => {
+                Primitive::with_material(faces, material, materials[material].shader()?)
+            }
+            // The default glTF material is white and opaque
+            None => {
+                let mut primitive = Primitive::new(faces);
+                primitive.shader = Material::new("").shader()?;
+                primitive
+            }
+        });
+    }
+
+    let face_count = faces.len();
+    let mut result = Mesh::new(vertices, faces, uvs);
+    if has_normals {
+        result.normals = normals;
+    }
+    if has_tangents {
+        result.tangents = tangents;
+    }
+    result.primitives = primitives;
+    result.materials = materials.to_vec();
+    result.groups = vec![Group {
+        name: name.to_string(),
+        faces: 0..face_count,
+    }];
+    result.transformation.position = Vector3::new(translation[0], translation[1], translation[2]);
+    result.transformation.quaternion =
+        Vector4::new(rotation[0], rotation[1], rotation[2], rotation[3]);
+    result.transformation.generate_affine_matrix();
+    Ok(result)
+}
+
+/// Decoded glTF images, by index and color space
+type GltfTextures = HashMap<(usize, ColorSpace), Arc<Texture>>;
+
+fn gltf_material(
+    path: &Path,
+    material: &gltf::Material,
+    images: &[gltf::image::Data],
+    textures: &mut GltfTextures,
+) -> Result<Material, LoadError> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let mut result = Material::new(material.name().unwrap_or_default());
+    result.diffuse = color_from_floats(r, g, b, 1.);
+    result.opacity = a;
+    result.metallic = pbr.metallic_factor();
+    result.roughness = pbr.roughness_factor();
+    result.alpha_mode = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend(Blend::ALPHA),
+    };
+    // The path of the texture's file (only textures in their own file have one) and the decoded
+    // image, with its mipmaps
+    let mut load = |texture: &gltf::Texture, color_space| -> Result<_, LoadError> {
+        let image = texture.source();
+        let map = match image.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                Some(path.parent().unwrap_or(Path::new("")).join(uri))
+            }
+            _ => None,
+        };
+        let decoded = match textures.get(&(image.index(), color_space)) {
+            Some(decoded) => decoded.clone(),
+            None => {
+                let mut decoded = gltf_texture(path, &images[image.index()], color_space)?;
+                decoded.generate_mipmaps();
+                let decoded = Arc::new(decoded);
+                textures.insert((image.index(), color_space), decoded.clone());
+                decoded
+            }
+        };
+        Ok((map, Some(decoded)))
+    };
+    if let Some(info) = pbr.base_color_texture() {
+        let texture = info.texture();
+        (result.diffuse_map, result.diffuse_texture) = load(&texture, ColorSpace::Srgb)?;
+        result.sampler = gltf_sampler(&texture.sampler());
+    }
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        (
+            result.metallic_roughness_map,
+            result.metallic_roughness_texture,
+        ) = load(&info.texture(), ColorSpace::Linear)?;
+    }
+    if let Some(info) = material.normal_texture() {
+        (result.normal_map, result.normal_texture) = load(&info.texture(), ColorSpace::Linear)?;
+    }
+    Ok(result)
+}
+
+/// Converts a decoded glTF image to a texture. Base color textures are sRGB, the others linear.
+fn gltf_texture(
+    path: &Path,
+    image: &gltf::image::Data,
+    color_space: ColorSpace,
+) -> Result<Texture, LoadError> {
+    use gltf::image::Format;
+    use image::{DynamicImage, ImageBuffer};
+    let (width, height, bytes) = (image.width, image.height, &image.pixels);
+    // Channels with more than 8 bits are stored in native endianness
+    let shorts = || {
+        bytes
+            .chunks_exact(2)
+            .map(|short| u16::from_ne_bytes([short[0], short[1]]))
+            .collect::<Vec<u16>>()
+    };
+    let floats = || {
+        bytes
+            .chunks_exact(4)
+            .map(|float| f32::from_ne_bytes(float.try_into().unwrap()))
+            .collect::<Vec<f32>>()
+    };
+    let converted = match image.format {
+        Format::R8 => {
+            ImageBuffer::from_raw(width, height, bytes.clone()).map(DynamicImage::ImageLuma8)
+        }
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, bytes.clone()).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => {
+            ImageBuffer::from_raw(width, height, bytes.clone()).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, bytes.clone()).map(DynamicImage::ImageRgba8)
+        }
+        Format::R16 => {
+            ImageBuffer::from_raw(width, height, shorts()).map(DynamicImage::ImageLuma16)
+        }
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, shorts()).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, shorts()).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, shorts()).map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgba32F)
+        }
+    };
+    match converted {
+        Some(converted) => Ok(Texture::from_image(&converted, color_space)),
+        // The image has less pixels than its size says
+        None => {
+            let error = image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            );
+            Err(LoadError::new(
+                path,
+                None,
+                LoadErrorKind::InvalidTexture(image::ImageError::Parameter(error)),
+            ))
+        }
+    }
+}
+
+/// Converts the filtering (when magnified), mipmapping and wrapping of a glTF sampler
+fn gltf_sampler(sampler: &gltf::texture::Sampler) -> Sampler {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    let wrap = |mode| match mode {
+        WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        WrappingMode::Repeat => Wrap::Repeat,
+    };
+    Sampler {
+        filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => Filter::Nearest,
+            // Linear, or up to the renderer
+            _ => Filter::Bilinear,
+        },
+        mipmaps: match sampler.min_filter() {
+            Some(MinFilter::Nearest | MinFilter::Linear) => Mipmaps::Off,
+            Some(MinFilter::NearestMipmapNearest | MinFilter::LinearMipmapNearest) => {
+                Mipmaps::Nearest
+            }
+            _ => Mipmaps::Linear,
+        },
+        anisotropy: 1,
+        wrap_u: wrap(sampler.wrap_s()),
+        wrap_v: wrap(sampler.wrap_t()),
+    }
+}
+
+//
+// STL
+//
+/// Loads an ASCII or binary .stl file. Vertices shared by several facets are welded together, and
+/// facets are flipped if their winding doesn't match their normal. Each solid of an ASCII file is
+/// a group.
+pub fn load_stl(file_path: &str) -> Result<Mesh, LoadError> {
+    let path = Path::new(file_path);
+    let data =
+        fs::read(path).map_err(|error| LoadError::new(path, None, LoadErrorKind::Io(error)))?;
+    // Binary files can start with "solid" too, but their size matches their facet count (unless
+    // truncated) and they have zero bytes, unlike text
+    let binary_size = data
+        .get(80..84)
+        .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    let mut builder = StlBuilder::default();
+    if data.starts_with(b"solid") && binary_size != Some(data.len()) && !data.contains(&0) {
+        read_ascii_stl(path, &String::from_utf8_lossy(&data), &mut builder)?;
+    } else {
+        let error = || LoadError::new(path, None, LoadErrorKind::UnexpectedEnd);
+        let size = binary_size.ok_or_else(error)?;
+        let facets = data.get(84..size).ok_or_else(error)?;
+        let float = |bytes: &[u8], i: usize| {
+            f32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap())
+        };
+        let vector = |bytes: &[u8], i: usize| {
+            Vector3::new(float(bytes, i), float(bytes, i + 1), float(bytes, i + 2))
+        };
+        for facet in facets.chunks_exact(50) {
+            builder.facet(
+                vector(facet, 0),
+                [vector(facet, 3), vector(facet, 6), vector(facet, 9)],
+            );
+        }
+    }
+    Ok(builder.build())
+}
+
+fn read_ascii_stl(path: &Path, contents: &str, builder: &mut StlBuilder) -> Result<(), LoadError> {
+    let mut normal = Vector3::new(0., 0., 0.);
+    let mut corners = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let error = |kind| LoadError::new(path, Some(i + 1), kind);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let vector = |start: usize| {
+            if tokens.len() < start + 3 {
+                return Err(error(LoadErrorKind::MissingValues {
+                    expected: start + 3,
+                    found: tokens.len(),
+                }));
+            }
+            let float = |token: &str| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| error(LoadErrorKind::MalformedFloat(token.to_string())))
+            };
+            Ok(Vector3::new(
+                float(tokens[start])?,
+                float(tokens[start + 1])?,
+                float(tokens[start + 2])?,
+            ))
+        };
+        match tokens.first() {
+            Some(&"solid") => builder.solid(tokens[1..].join(" ")),
+            // "facet normal nx ny nz"
+            Some(&"facet") => {
+                normal = vector(2)?;
+                corners.clear();
+            }
+            Some(&"vertex") => corners.push(vector(1)?),
+            Some(&"endfacet") => {
+                if corners.len() != 3 {
+                    return Err(error(LoadErrorKind::MissingValues {
+                        expected: 3,
+                        found: corners.len(),
+                    }));
+                }
+                builder.facet(normal, [corners[0], corners[1], corners[2]]);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Welds the vertices of STL facets as they are read
+#[derive(Default)]
+struct StlBuilder {
+    vertices: Vec<Vector3>,
+    normals: Vec<Vector3>,
+    faces: Vec<Face>,
+    groups: Vec<Group>,
+    /// Index of each vertex from the bits of its coordinates
+    indices: HashMap<[u32; 3], usize>,
+}
+
+impl StlBuilder {
+    fn solid(&mut self, name: String) {
+        if let Some(group) = self.groups.last_mut() {
+            group.faces.end = self.faces.len();
+        }
+        self.groups.push(Group {
+            name,
+            faces: self.faces.len()..self.faces.len(),
+        });
+    }
+
+    fn vertex(&mut self, vertex: Vector3) -> usize {
+        // Adding 0 turns -0 into 0, so that they are welded together
+        let key = [vertex.x + 0., vertex.y + 0., vertex.z + 0.].map(f32::to_bits);
+        *self.indices.entry(key).or_insert_with(|| {
+            self.vertices.push(vertex);
+            self.vertices.len() - 1
+        })
+    }
+
+    fn facet(&mut self, normal: Vector3, [a, b, c]: [Vector3; 3]) {
+        let (a, b, c) = (self.vertex(a), self.vertex(b), self.vertex(c));
+        // Degenerate facets (from welding or in the file) can't be drawn
+        if a == b || b == c || c == a {
+            return;
+        }
+        let mut face = Face::new(a, b, c, 0, 0, 0);
+        face.compute_normal(&self.vertices);
+        // Zero facet normals mean that it should be computed from the winding
+        if normal.dot_product(&normal) > 0. {
+            if face.normal.dot_product(&normal) < 0. {
+                face = Face::new(a, c, b, 0, 0, 0);
+                face.compute_normal(&self.vertices);
+            }
+            self.normals.push(normal.normalised());
+            let index = Some(self.normals.len() - 1);
+            (face.na, face.nb, face.nc) = (index, index, index);
+        }
+        self.faces.push(face);
+    }
+
+    fn build(mut self) -> Mesh {
+        if let Some(group) = self.groups.last_mut() {
+            group.faces.end = self.faces.len();
+        }
+        self.groups.retain(|group| !group.faces.is_empty());
+        // STL has no UVs, every face uses the same placeholder
+        let mut mesh = Mesh::new(self.vertices, self.faces, vec![Vector2::new(0., 0.)]);
+        mesh.normals = self.normals;
+        mesh.groups = self.groups;
+        mesh
+    }
+}
+
+//
+// PLY
+//
+#[derive(Copy, Clone, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Option<PlyType> {
+        Some(match name {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    /// Value of a fully saturated color channel: 1 for floats, the largest value for integers
+    fn full_channel(self) -> f64 {
+        match self {
+            PlyType::I8 => i8::MAX as f64,
+            PlyType::U8 => u8::MAX as f64,
+            PlyType::I16 => i16::MAX as f64,
+            PlyType::U16 => u16::MAX as f64,
+            PlyType::I32 => i32::MAX as f64,
+            PlyType::U32 => u32::MAX as f64,
+            PlyType::F32 | PlyType::F64 => 1.,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    kind: PlyType,
+    /// Type of the length of the list, for list properties
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads the values of PLY elements one after the other, from text or binary data
+struct PlyReader<'a> {
+    path: &'a Path,
+    format: PlyFormat,
+    data: &'a [u8],
+    position: usize,
+    /// Line of the current element (ASCII only)
+    line: usize,
+    tokens: Vec<&'a str>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn error(&self, kind: LoadErrorKind) -> LoadError {
+        let line = (self.format == PlyFormat::Ascii).then_some(self.line);
+        LoadError::new(self.path, line, kind)
+    }
+
+    /// Moves to the next element. Elements are on their own line in ASCII files.
+    fn next_element(&mut self) -> Result<(), LoadError> {
+        if self.format != PlyFormat::Ascii {
+            return Ok(());
+        }
+        loop {
+            let rest = &self.data[self.position..];
+            if rest.is_empty() {
+                return Err(self.error(LoadErrorKind::UnexpectedEnd));
+            }
+            let end = rest
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .unwrap_or(rest.len());
+            self.position += (end + 1).min(rest.len());
+            self.line += 1;
+            let line = std::str::from_utf8(&rest[..end]).unwrap_or_default();
+            self.tokens = line.split_whitespace().rev().collect();
+            if !self.tokens.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read(&mut self, kind: PlyType) -> Result<f64, LoadError> {
+        if self.format == PlyFormat::Ascii {
+            let token = self
+                .tokens
+                .pop()
+                .ok_or_else(|| self.error(LoadErrorKind::UnexpectedEnd))?;
+            return token
+                .parse()
+                .map_err(|_| self.error(LoadErrorKind::MalformedFloat(token.to_string())));
+        }
+        let bytes = self
+            .data
+            .get(self.position..self.position + kind.size())
+            .ok_or_else(|| self.error(LoadErrorKind::UnexpectedEnd))?;
+        self.position += kind.size();
+        let mut buffer = [0u8; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        if self.format == PlyFormat::BigEndian {
+            buffer[..bytes.len()].reverse();
+        }
+        Ok(match kind {
+            PlyType::I8 => buffer[0] as i8 as f64,
+            PlyType::U8 => buffer[0] as f64,
+            PlyType::I16 => i16::from_le_bytes(buffer[..2].try_into().unwrap()) as f64,
+            PlyType::U16 => u16::from_le_bytes(buffer[..2].try_into().unwrap()) as f64,
+            PlyType::I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            PlyType::U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            PlyType::F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            PlyType::F64 => f64::from_le_bytes(buffer[..8].try_into().unwrap()),
+        })
+    }
+}
+
+/// Loads an ASCII or binary (little or big endian) .ply file. Vertex positions, normals, UVs and
+/// colors (with their alpha) are read, faces are triangulated. Files without faces give point
+/// clouds, files without normals get smooth ones.
+pub fn load_ply(file_path: &str) -> Result<Mesh, LoadError> {
+    let path = Path::new(file_path);
+    let data =
+        fs::read(path).map_err(|error| LoadError::new(path, None, LoadErrorKind::Io(error)))?;
+
+    // Header
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    let mut position = 0;
+    let mut line_number = 0;
+    loop {
+        let error = |kind| LoadError::new(path, Some(line_number + 1), kind);
+        let rest = &data[position..];
+        let Some(end) = rest.iter().position(|byte| *byte == b'\n') else {
+            return Err(error(LoadErrorKind::UnexpectedEnd));
+        };
+        position += end + 1;
+        line_number += 1;
+        let error = |kind| LoadError::new(path, Some(line_number), kind);
+        let line = String::from_utf8_lossy(&rest[..end]);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let expect = |expected: usize| {
+            if tokens.len() < expected {
+                Err(error(LoadErrorKind::MissingValues {
+                    expected,
+                    found: tokens.len(),
+                }))
+            } else {
+                Ok(())
+            }
+        };
+        let kind = |name: &str| {
+            PlyType::parse(name)
+                .ok_or_else(|| error(LoadErrorKind::Unsupported(format!("type '{}'", name))))
+        };
+        match tokens.first() {
+            Some(&"format") => {
+                expect(2)?;
+                format = Some(match tokens[1] {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    other => {
+                        return Err(error(LoadErrorKind::Unsupported(format!(
+                            "format '{}'",
+                            other
+                        ))))
+                    }
+                })
+            }
+            Some(&"element") => {
+                expect(3)?;
+                elements.push(PlyElement {
+                    name: tokens[1].to_string(),
+                    count: tokens[2]
+                        .parse()
+                        .map_err(|_| error(LoadErrorKind::BadIndex(tokens[2].to_string())))?,
+                    properties: Vec::new(),
+                });
+            }
+            Some(&"property") => {
+                // "property <type> <name>" or "property list <length type> <type> <name>"
+                let property = if tokens.get(1) == Some(&"list") {
+                    expect(5)?;
+                    PlyProperty {
+                        name: tokens[4].to_string(),
+                        kind: kind(tokens[3])?,
+                        list: Some(kind(tokens[2])?),
+                    }
+                } else {
+                    expect(3)?;
+                    PlyProperty {
+                        name: tokens[2].to_string(),
+                        kind: kind(tokens[1])?,
+                        list: None,
+                    }
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| {
+                        error(LoadErrorKind::Unsupported(
+                            "property outside of an element".to_string(),
+                        ))
+                    })?
+                    .properties
+                    .push(property);
+            }
+            Some(&"end_header") => break,
+            // ply, comment, obj_info
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| {
+        LoadError::new(
+            path,
+            None,
+            LoadErrorKind::Unsupported("file without a format".to_string()),
+        )
+    })?;
+
+    // Body
+    let mut reader = PlyReader {
+        path,
+        format,
+        data: &data,
+        position,
+        line: line_number,
+        tokens: Vec::new(),
+    };
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut polygons: Vec<(usize, Vec<usize>)> = Vec::new();
+    for element in &elements {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|property| names.contains(&property.name.as_str()))
+        };
+        let (x, y, z) = (find(&["x"]), find(&["y"]), find(&["z"]));
+        let normal = (find(&["nx"]), find(&["ny"]), find(&["nz"]));
+        let uv = (
+            find(&["s", "u", "texture_u"]),
+            find(&["t", "v", "texture_v"]),
+        );
+        let color = (
+            find(&["red", "r", "diffuse_red"]),
+            find(&["green", "g", "diffuse_green"]),
+            find(&["blue", "b", "diffuse_blue"]),
+        );
+        let alpha = find(&["alpha", "a", "diffuse_alpha"]);
+        let indices = find(&["vertex_indices", "vertex_index"]);
+        // Colors are floats from 0 to 1, or integers up to the largest value of their type
+        let channel = |value: f64, property: usize| {
+            let full = element.properties[property].kind.full_channel();
+            ((value / full).clamp(0., 1.) * 255.).round() as u8
+        };
+
+        for _ in 0..element.count {
+            reader.next_element()?;
+            let mut values = Vec::with_capacity(element.properties.len());
+            let mut list = Vec::new();
+            for (i, property) in element.properties.iter().enumerate() {
+                match property.list {
+                    Some(length) => {
+                        let length = reader.read(length)? as usize;
+                        let items = (0..length)
+                            .map(|_| reader.read(property.kind))
+                            .collect::<Result<Vec<f64>, LoadError>>()?;
+                        if Some(i) == indices {
+                            list = items;
+                        }
+                        values.push(0.);
+                    }
+                    None => values.push(reader.read(property.kind)?),
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |i: Option<usize>| i.map(|i| values[i] as f32).unwrap_or(0.);
+                    vertices.push(Vector3::new(get(x), get(y), get(z)));
+                    if let (Some(_), Some(_), Some(_)) = normal {
+                        normals.push(Vector3::new(get(normal.0), get(normal.1), get(normal.2)));
+                    }
+                    if let (Some(_), Some(_)) = uv {
+                        // Like .obj files, v goes up
+                        uvs.push(Vector2::new(get(uv.0), 1. - get(uv.1)));
+                    }
+                    if let (Some(r), Some(g), Some(b)) = color {
+                        colors.push(Color(
+                            channel(values[r], r),
+                            channel(values[g], g),
+                            channel(values[b], b),
+                            alpha.map_or(255, |a| channel(values[a], a)),
+                        ));
+                    }
+                }
+                "face" => {
+                    // They would be turned into other vertices
+                    let invalid = |index: &&f64| **index < 0. || index.fract() != 0.;
+                    if let Some(index) = list.iter().find(invalid) {
+                        return Err(reader.error(LoadErrorKind::BadIndex(index.to_string())));
+                    }
+                    let polygon = list.iter().map(|index| *index as usize).collect();
+                    polygons.push((reader.line, polygon));
                 }
                 _ => {}
             }
         }
     }
 
-    Mesh::new(vertices, faces, uvs)
+    let mut faces = Vec::new();
+    for (line, polygon) in polygons {
+        if let Some(index) = polygon.iter().find(|index| **index >= vertices.len()) {
+            let line = (format == PlyFormat::Ascii).then_some(line);
+            return Err(LoadError::new(
+                path,
+                line,
+                LoadErrorKind::BadIndex(index.to_string()),
+            ));
+        }
+        let positions: Vec<Vector3> = polygon.iter().map(|index| vertices[*index]).collect();
+        for [a, b, c] in triangulate(&positions) {
+            let (a, b, c) = (polygon[a], polygon[b], polygon[c]);
+            let mut face = if uvs.is_empty() {
+                Face::new(a, b, c, 0, 0, 0)
+            } else {
+                Face::new(a, b, c, a, b, c)
+            };
+            if !normals.is_empty() {
+                (face.na, face.nb, face.nc) = (Some(a), Some(b), Some(c));
+            }
+            face.compute_normal(&vertices);
+            faces.push(face);
+        }
+    }
+
+    if uvs.is_empty() {
+        uvs.push(Vector2::new(0., 0.));
+    }
+    let mut mesh = Mesh::new(vertices, faces, uvs);
+    if !colors.is_empty() {
+        mesh.set_shader(Box::new(VertexColorShader));
+    }
+    mesh.normals = normals;
+    mesh.generate_normals(DEFAULT_CREASE_ANGLE);
+    mesh.colors = colors;
+    Ok(mesh)
 }
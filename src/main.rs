@@ -1,10 +1,4 @@
-mod camera;
-mod graphics;
-mod loader;
-mod math;
-mod mesh;
-mod shaders;
-mod world;
+use renderer::{camera, graphics, loader, math, world};
 
 pub fn main() {
     let file_path = "models/quad_damage/quad_damage.obj";
@@ -15,27 +9,27 @@ pub fn main() {
         600,
     );
 
+    renderer.lighting = graphics::Lighting::BlinnPhong;
+
     let mut world = world::World {
-        camera: camera::PerspectiveCamera::new(&math::Vector3::new(0., 0., -20.), 800., 600.),
+        camera: camera::PerspectiveCamera::new(&math::Vector3::new(0., 0., -2.), 800., 600.),
         light: world::DirectionalLight::new(&math::Vector3::new(0., -1., 0.), 0.5),
         ambient: 0.2,
     };
 
     world.camera.generate_projection_matrix();
 
-    let mut mesh_loaded = loader::load(file_path);
-
-    let shader = shaders::TextureShader::new(
-        shaders::TextureShader::image2vec("models/quad_damage/texture.png")
-            .expect("can't load the image... because of woke"),
-        308.,
-        121.,
-    );
-    mesh_loaded.shader = Box::new(shader);
+    let mut mesh_loaded = match loader::load(file_path) {
+        Ok(mesh) => mesh,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
 
     let mut keyframe = 0.;
 
-    mesh_loaded.transformation.quaternion.y = std::f32::consts::FRAC_1_SQRT_2;
+    mesh_loaded.transformation.quaternion.y = -std::f32::consts::FRAC_1_SQRT_2;
     mesh_loaded.transformation.quaternion.w = std::f32::consts::FRAC_1_SQRT_2;
 
     while renderer.is_running() {
@@ -47,9 +41,9 @@ pub fn main() {
             keyframe += 0.01 * std::f32::consts::PI;
         }
 
-        world.camera.position.y = &keyframe.sin() * 0.2;
+        world.camera.position.y = &keyframe.sin() * 0.02;
 
-        mesh_loaded.transformation.quaternion.y = (keyframe / 2.).sin();
+        mesh_loaded.transformation.quaternion.y = -(keyframe / 2.).sin();
         mesh_loaded.transformation.quaternion.w = (keyframe / 2.).cos();
         mesh_loaded.transformation.quaternion = mesh_loaded.transformation.quaternion.normalised();
         mesh_loaded.transformation.generate_affine_matrix();
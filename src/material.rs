@@ -0,0 +1,162 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    graphics::{AlphaMode, Color},
+    loader::LoadError,
+    shaders::{Shader, Specular, StandardShader, TextureShader},
+    texture::{ColorSpace, Sampler, Texture, Wrap},
+};
+
+/// Surface properties of a part of a mesh, as found in .mtl files and glTF materials.
+/// Texture paths are only known for textures in their own file, not embedded ones. Decoded
+/// textures are shared between the copies of a material and the shaders made from it.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// Ka
+    pub ambient: Color,
+    /// Kd
+    pub diffuse: Color,
+    /// Ks
+    pub specular: Color,
+    /// Ns, the specular exponent
+    pub shininess: f32,
+    /// d (or 1 - Tr), from 0 (transparent) to 1 (opaque)
+    pub opacity: f32,
+    /// map_Kd
+    pub diffuse_map: Option<PathBuf>,
+    /// map_Bump (or bump)
+    pub bump_map: Option<PathBuf>,
+    /// map_d
+    pub alpha_map: Option<PathBuf>,
+    /// glTF metallic factor, from 0 to 1
+    pub metallic: f32,
+    /// glTF roughness factor, from 0 to 1
+    pub roughness: f32,
+    /// glTF metallic (blue) and roughness (green) texture
+    pub metallic_roughness_map: Option<PathBuf>,
+    /// glTF tangent space normal map
+    pub normal_map: Option<PathBuf>,
+    /// The decoded diffuse map (or embedded texture), with its mipmaps
+    pub diffuse_texture: Option<Arc<Texture>>,
+    /// The decoded alpha map, with its mipmaps
+    pub alpha_texture: Option<Arc<Texture>>,
+    /// The decoded metallic and roughness texture, with its mipmaps
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    /// The decoded normal map, with its mipmaps
+    pub normal_texture: Option<Arc<Texture>>,
+    /// How the textures are sampled
+    pub sampler: Sampler,
+    /// Whether the material is opaque, cut out or blended. Loaders derive it from the opacity and
+    /// the alpha map (.mtl) or read it (glTF).
+    pub alpha_mode: AlphaMode,
+}
+
+impl Material {
+    /// Creates a white, opaque material without textures
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: Color::rgb(0, 0, 0),
+            diffuse: Color::rgb(255, 255, 255),
+            specular: Color::rgb(0, 0, 0),
+            shininess: 0.,
+            opacity: 1.,
+            diffuse_map: None,
+            bump_map: None,
+            alpha_map: None,
+            metallic: 0.,
+            roughness: 1.,
+            metallic_roughness_map: None,
+            normal_map: None,
+            diffuse_texture: None,
+            alpha_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            sampler: Sampler::trilinear(Wrap::Repeat),
+            alpha_mode: AlphaMode::Opaque,
+        }
+    }
+
+    /// Decodes the maps that don't have a texture yet, so that the shaders made from the material
+    /// share them instead of each loading its own
+    pub fn load_textures(&mut self) -> Result<(), LoadError> {
+        self.diffuse_texture = load_texture(&self.diffuse_texture, &self.diffuse_map, None)?;
+        // Alpha, metallic, roughness and normals aren't colors
+        let linear = Some(ColorSpace::Linear);
+        self.alpha_texture = load_texture(&self.alpha_texture, &self.alpha_map, linear)?;
+        self.metallic_roughness_texture = load_texture(
+            &self.metallic_roughness_texture,
+            &self.metallic_roughness_map,
+            linear,
+        )?;
+        self.normal_texture = load_texture(&self.normal_texture, &self.normal_map, linear)?;
+        Ok(())
+    }
+
+    /// Creates the shader that draws this material: textured if it has a diffuse texture or map,
+    /// tinted by the diffuse color, and a plain diffuse color otherwise. Highlights come from the
+    /// specular color and exponent. Maps that haven't been loaded (see load_textures) are loaded
+    /// for this shader only.
+    pub fn shader(&self) -> Result<Box<dyn Shader>, LoadError> {
+        let specular = Specular::new(self.specular, self.shininess);
+        let color = self
+            .diffuse
+            .with_alpha((self.opacity.clamp(0., 1.) * 255.).round() as u8);
+        let alpha_map = load_texture(
+            &self.alpha_texture,
+            &self.alpha_map,
+            Some(ColorSpace::Linear),
+        )?;
+        let texture = match load_texture(&self.diffuse_texture, &self.diffuse_map, None)? {
+            Some(texture) => Some(texture),
+            // The alpha map needs UVs, so the diffuse color becomes a texture
+            None if alpha_map.is_some() => {
+                let white = Texture::new(vec![Color::rgb(255, 255, 255)], 1, 1);
+                Some(Arc::new(white.expect("one pixel for a 1x1 texture")))
+            }
+            None => None,
+        };
+        Ok(match texture {
+            Some(texture) => {
+                let mut shader = TextureShader::shared(texture);
+                shader.sampler = self.sampler;
+                shader.color = color;
+                shader.alpha_map = alpha_map;
+                shader.specular = specular;
+                shader.alpha_mode = self.alpha_mode;
+                Box::new(shader)
+            }
+            None => {
+                let mut shader = StandardShader::new(color);
+                shader.specular = specular;
+                shader.alpha_mode = self.alpha_mode;
+                Box::new(shader)
+            }
+        })
+    }
+}
+
+/// The texture if it is already decoded, otherwise the map's file (if any) decoded with its
+/// mipmaps. The color space of the file is kept unless one is given.
+fn load_texture(
+    texture: &Option<Arc<Texture>>,
+    map: &Option<PathBuf>,
+    color_space: Option<ColorSpace>,
+) -> Result<Option<Arc<Texture>>, LoadError> {
+    if texture.is_some() {
+        return Ok(texture.clone());
+    }
+    let Some(path) = map else {
+        return Ok(None);
+    };
+    let mut texture = Texture::load(Path::new(path))?;
+    if let Some(color_space) = color_space {
+        texture.set_color_space(color_space);
+    }
+    texture.generate_mipmaps();
+    Ok(Some(Arc::new(texture)))
+}
@@ -1,13 +1,21 @@
-use std::ops::Deref;
+use std::collections::HashMap;
+use std::ops::Range;
 
+use crate::clipping;
+use crate::clipping::ClipVertex;
+use crate::graphics::AlphaMode;
+use crate::graphics::Attributes;
 use crate::graphics::Color;
+use crate::graphics::Derivatives;
+use crate::graphics::Lighting;
 use crate::graphics::Renderer;
+use crate::graphics::Triangle;
+use crate::material::Material;
 use crate::math::Matrix44;
 use crate::math::Vector2;
 use crate::math::Vector3;
 use crate::math::Vector4;
 use crate::shaders;
-use crate::shaders::Shader;
 use crate::shaders::StandardShader;
 use crate::world::World;
 
@@ -23,6 +31,12 @@ pub struct Face {
     pub uva: usize,
     pub uvb: usize,
     pub uvc: usize,
+    /// Indices of the vertex normals in the mesh's normals (if the file had any)
+    pub na: Option<usize>,
+    pub nb: Option<usize>,
+    pub nc: Option<usize>,
+    /// Smoothing group of the face: 0 when it is flat (s off), None when the file doesn't say
+    pub smoothing_group: Option<u32>,
 }
 
 impl Face {
@@ -37,6 +51,10 @@ impl Face {
             uva,
             uvb,
             uvc,
+            na: None,
+            nb: None,
+            nc: None,
+            smoothing_group: None,
             normal: Vector3::new(0., 0., 0.),
         }
     }
@@ -63,99 +81,366 @@ impl Transformation {
         self.affine_matrix.multiply_vec3(point)
     }
 
+    /// Rotates a direction (a normal, ...) without moving it
+    pub fn rotated(&self, direction: &Vector3) -> Vector3 {
+        self.transformed(direction) - self.position
+    }
+
     /// Generates an affine transformation matrix. Needs to be called after the position/quaternion
     /// is mutated.
     pub fn generate_affine_matrix(&mut self) {
+        let Vector4 { x, y, z, w } = self.quaternion;
+        // Row-major, like every other Matrix44
         self.affine_matrix.data = vec![
-            1. - (2. * self.quaternion.y.powi(2) + 2. * self.quaternion.z.powi(2)),
-            2. * self.quaternion.x * self.quaternion.y + 2. * self.quaternion.z * self.quaternion.w,
-            2. * self.quaternion.x * self.quaternion.z - 2. * self.quaternion.y * self.quaternion.w,
-            0.,
-            2. * self.quaternion.x * self.quaternion.y - 2. * self.quaternion.w * self.quaternion.z,
-            1. - (2. * self.quaternion.x.powi(2) + 2. * self.quaternion.z.powi(2)),
-            2. * self.quaternion.y * self.quaternion.z + 2. * self.quaternion.w * self.quaternion.x,
-            0.,
-            2. * self.quaternion.x * self.quaternion.z + 2. * self.quaternion.w * self.quaternion.y,
-            2. * self.quaternion.y * self.quaternion.z - 2. * self.quaternion.w * self.quaternion.x,
-            1. - (2. * self.quaternion.x.powi(2) + 2. * self.quaternion.y.powi(2)),
-            0.,
+            1. - 2. * (y * y + z * z),
+            2. * (x * y - z * w),
+            2. * (x * z + y * w),
             self.position.x,
+            2. * (x * y + z * w),
+            1. - 2. * (x * x + z * z),
+            2. * (y * z - x * w),
             self.position.y,
+            2. * (x * z - y * w),
+            2. * (y * z + x * w),
+            1. - 2. * (x * x + y * y),
             self.position.z,
+            0.,
+            0.,
+            0.,
             1.,
         ]
     }
 }
 
+//
+// Groups
+//
+/// A named range of faces (`o` and `g` in .obj files)
+#[derive(Clone)]
+pub struct Group {
+    pub name: String,
+    pub faces: Range<usize>,
+}
+
+//
+// Primitives
+//
+/// A range of faces drawn with the same shader, usually made from a material
+pub struct Primitive {
+    pub faces: Range<usize>,
+    /// Index of the material in the mesh's materials
+    pub material: Option<usize>,
+    pub shader: Box<dyn shaders::Shader>,
+}
+
+impl Primitive {
+    /// Creates a primitive drawn in plain white
+    pub fn new(faces: Range<usize>) -> Primitive {
+        Primitive {
+            faces,
+            material: None,
+            shader: Box::new(StandardShader::new(Color::rgb(255, 255, 255))),
+        }
+    }
+
+    pub fn with_material(
+        faces: Range<usize>,
+        material: usize,
+        shader: Box<dyn shaders::Shader>,
+    ) -> Primitive {
+        Primitive {
+            faces,
+            material: Some(material),
+            shader,
+        }
+    }
+}
+
 //
 // Meshes
 //
+/// Largest angle between two faces for them to be smoothed together by default (60°, in radians)
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
 pub struct Mesh {
     pub vertices: Vec<Vector3>,
     pub faces: Vec<Face>,
     pub uvs: Vec<Vector2>,
+    pub normals: Vec<Vector3>,
+    /// Color of each vertex. Empty if the model doesn't have any.
+    pub colors: Vec<Color>,
+    /// Tangents of each vertex, with the handedness of the bitangent in w. Empty if the model
+    /// doesn't have any.
+    pub tangents: Vec<Vector4>,
+    pub groups: Vec<Group>,
+    pub materials: Vec<Material>,
     pub transformation: Transformation,
-    pub shader: Box<dyn shaders::Shader>,
+    pub primitives: Vec<Primitive>,
 }
 
 impl Mesh {
+    /// Creates a mesh with a single white primitive
     pub fn new(vertices: Vec<Vector3>, faces: Vec<Face>, uvs: Vec<Vector2>) -> Mesh {
+        let primitives = vec![Primitive::new(0..faces.len())];
         Mesh {
             vertices,
             faces,
             uvs,
+            normals: Vec::new(),
+            colors: Vec::new(),
+            tangents: Vec::new(),
+            groups: Vec::new(),
+            materials: Vec::new(),
             transformation: Transformation {
                 position: Vector3::new(0., 0., 0.),
                 scale: 1.0,
                 quaternion: Vector4::new(0., 0., 0., 1.),
                 affine_matrix: Matrix44 { data: Vec::new() },
             },
-            shader: Box::new(StandardShader::new(Color(255, 255, 255))),
+            primitives,
+        }
+    }
+
+    /// Draws the whole mesh with one shader, replacing its primitives
+    pub fn set_shader(&mut self, shader: Box<dyn shaders::Shader>) {
+        self.primitives = vec![Primitive {
+            faces: 0..self.faces.len(),
+            material: None,
+            shader,
+        }];
+    }
+
+    /// Gives a vertex normal to every corner of a face that doesn't have one, averaging the normals
+    /// of the faces around its vertex weighted by their area and the angle of their corner.
+    /// Faces are only smoothed together if they are in the same smoothing group and the angle
+    /// between them is at most `crease_angle` (in radians). Flat faces (smoothing group 0) get
+    /// their own normal, and faces without a smoothing group are smoothed with each other by the
+    /// crease angle only.
+    pub fn generate_normals(&mut self, crease_angle: f32) {
+        // Vertices can be duplicated (at UV seams...), so corners are matched by position
+        let key = |vertex: &Vector3| [vertex.x, vertex.y, vertex.z].map(|x| (x + 0.).to_bits());
+        // Weighted normal of each face around every vertex position
+        let mut around: HashMap<[u32; 3], Vec<(usize, Vector3)>> = HashMap::new();
+        for (index, face) in self.faces.iter().enumerate() {
+            let [a, b, c] = [face.a, face.b, face.c].map(|vertex| self.vertices[vertex]);
+            let area = (b - a).cross_product(&(c - a)).length() / 2.;
+            for (vertex, p, q) in [(a, b, c), (b, c, a), (c, a, b)] {
+                let angle = (p - vertex)
+                    .cos_similarity(&(q - vertex))
+                    .clamp(-1., 1.)
+                    .acos();
+                let weighted = face.normal * (area * angle);
+                if weighted.length().is_finite() && weighted.length() > 0. {
+                    around
+                        .entry(key(&vertex))
+                        .or_default()
+                        .push((index, weighted));
+                }
+            }
+        }
+
+        let threshold = crease_angle.cos();
+        let mut indices: HashMap<[u32; 3], usize> = HashMap::new();
+        for index in 0..self.faces.len() {
+            let face = self.faces[index];
+            let corners = [(face.a, face.na), (face.b, face.nb), (face.c, face.nc)];
+            let normals = corners.map(|(vertex, normal)| {
+                if normal.is_some() {
+                    return normal;
+                }
+                let normal = if face.smoothing_group == Some(0) {
+                    face.normal
+                } else {
+                    let mut sum = Vector3::new(0., 0., 0.);
+                    for (other, weighted) in around.get(&key(&self.vertices[vertex]))? {
+                        let other = &self.faces[*other];
+                        if other.smoothing_group == face.smoothing_group
+                            && other.normal.dot_product(&face.normal) >= threshold
+                        {
+                            sum = sum + *weighted;
+                        }
+                    }
+                    sum.normalised()
+                };
+                if !normal.length().is_finite() {
+                    return None;
+                }
+                Some(*indices.entry(key(&normal)).or_insert_with(|| {
+                    self.normals.push(normal);
+                    self.normals.len() - 1
+                }))
+            });
+            let face = &mut self.faces[index];
+            [face.na, face.nb, face.nc] = normals;
         }
     }
 
     /// Draws the mesh onto the screen. Must be called after an affine matrix is generated for its
-    /// transformation (see Transformation.generate_affine_matrix)
+    /// transformation (see Transformation.generate_affine_matrix).
+    /// Faces of primitives that blend (see AlphaMode::Blend) are drawn after the others, from the
+    /// farthest to the nearest. Blended meshes should be drawn after opaque ones for the same
+    /// reason. Meshes without faces (point clouds) are drawn as points.
     pub fn draw(&mut self, renderer: &mut Renderer, world: &World) {
-        let vertices_projected: Vec<Vector3> = self
-            .vertices
-            .clone()
+        if self.faces.is_empty() {
+            self.draw_points(renderer, world);
+            return;
+        }
+        let attributes: Vec<[Attributes; 3]> = self
+            .faces
             .iter()
-            .map(|vertex| {
-                world
-                    .camera
-                    .to_ndc(world.camera.project_point(vertex, &self.transformation))
-            })
+            .map(|face| [0, 1, 2].map(|corner| self.attributes(face, corner)))
             .collect();
-        for face in &self.faces {
-            let a = vertices_projected[face.a];
-            let b = vertices_projected[face.b];
-            let c = vertices_projected[face.c];
-            if (b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y) > 0. {
-                let brightness = self
+        // Faces that are blended, with the index of their primitive and their distance to the
+        // camera
+        let mut blended: Vec<(usize, f32, Triangle)> = Vec::new();
+        // Each primitive is drawn in turn, with its own shader
+        for (index, primitive) in self.primitives.iter_mut().enumerate() {
+            let blend = matches!(primitive.shader.alpha_mode(), AlphaMode::Blend(_));
+            let mut triangles = Vec::with_capacity(primitive.faces.len());
+            let faces = primitive.faces.clone();
+            for (face, corners) in self.faces[faces.clone()].iter().zip(&attributes[faces]) {
+                // Vertex stage
+                let mut corners = *corners;
+                let projected = corners
+                    .each_mut()
+                    .map(|corner| primitive.shader.vertex(corner, world));
+                if renderer.lighting == Lighting::Gouraud {
+                    for corner in &mut corners {
+                        corner.brightness =
+                            primitive.shader.calculate_lighting(&corner.normal, world);
+                    }
+                }
+                // Cut the parts of the face that are outside of the view (behind the camera, ...)
+                let polygon = clipping::clip_triangle(
+                    [0, 1, 2].map(|corner| ClipVertex::new(projected[corner], corners[corner])),
+                );
+                if polygon.len() < 3 {
+                    continue;
+                }
+                let screen: Vec<Vector3> = polygon
+                    .iter()
+                    .map(|vertex| world.camera.to_ndc(vertex.position))
+                    .collect();
+                // Back-face culling (shoelace formula, positive when the face is facing the camera)
+                let area: f32 = (0..screen.len())
+                    .map(|i| {
+                        let (p, q) = (screen[i], screen[(i + 1) % screen.len()]);
+                        p.x * q.y - q.x * p.y
+                    })
+                    .sum();
+                if area <= 0. {
+                    continue;
+                }
+                let a = screen[0];
+                let brightness = primitive
                     .shader
-                    .calculate_lighting(&self.transformation.transformed(&face.normal), world);
-                // TODO: Don't need to calculate perspective correct textures -- yet?
-                /*let aw = world
-                    .camera
-                    .get_w(&self.vertices[face.a], &self.transformation);
-                let bw = world
-                    .camera
-                    .get_w(&self.vertices[face.b], &self.transformation);
-                let cw = world
-                    .camera
-                    .get_w(&self.vertices[face.c], &self.transformation);*/
-                renderer.draw_triangle(
-                    &a,
-                    &b,
-                    &c,
-                    (&self.uvs[face.uva], 1.),
-                    (&self.uvs[face.uvb], 1.),
-                    (&self.uvs[face.uvc], 1.),
-                    self.shader.as_ref(),
+                    .calculate_lighting(&self.transformation.rotated(&face.normal), world);
+                // The polygon is convex, draw it as a fan
+                let fan = (1..(polygon.len() - 1)).map(|i| Triangle {
+                    a,
+                    b: screen[i],
+                    c: screen[i + 1],
+                    attributes: [
+                        polygon[0].attributes,
+                        polygon[i].attributes,
+                        polygon[i + 1].attributes,
+                    ],
+                    w: [
+                        polygon[0].position.w,
+                        polygon[i].position.w,
+                        polygon[i + 1].position.w,
+                    ],
                     brightness,
-                );
+                });
+                if blend {
+                    // w is the depth in view space
+                    let distance = projected.iter().map(|corner| corner.w).sum::<f32>() / 3.;
+                    blended.extend(fan.map(|triangle| (index, distance, triangle)));
+                } else {
+                    triangles.extend(fan);
+                }
+            }
+            if !blend {
+                renderer.draw_triangles(&triangles, primitive.shader.as_ref(), world);
+            }
+        }
+
+        // Back to front, so that faces are blended with the ones behind them. Faces at the same
+        // distance keep their order.
+        blended.sort_by(|(_, a, _), (_, b, _)| b.total_cmp(a));
+        for run in blended.chunk_by(|(a, _, _), (b, _, _)| a == b) {
+            let triangles: Vec<Triangle> = run.iter().map(|(_, _, triangle)| *triangle).collect();
+            let shader = self.primitives[run[0].0].shader.as_ref();
+            renderer.draw_triangles(&triangles, shader, world);
+        }
+    }
+
+    /// Attributes of a corner (0, 1 or 2) of a face, in world space. Corners
+    /// without a vertex normal use the face's.
+    fn attributes(&self, face: &Face, corner: usize) -> Attributes {
+        let (vertex, uv, normal) = match corner {
+            0 => (face.a, face.uva, face.na),
+            1 => (face.b, face.uvb, face.nb),
+            _ => (face.c, face.uvc, face.nc),
+        };
+        let normal = normal
+            .and_then(|normal| self.normals.get(normal))
+            .unwrap_or(&face.normal);
+        Attributes {
+            uv: self.uvs[uv],
+            color: self
+                .colors
+                .get(vertex)
+                .copied()
+                .unwrap_or(Color::rgb(255, 255, 255)),
+            normal: self.transformation.rotated(normal),
+            position: self.transformation.transformed(&self.vertices[vertex]),
+            ..Default::default()
+        }
+    }
+
+    /// Draws every vertex as a point, shaded by the first primitive's shader (as if it was the
+    /// first vertex of a triangle) and lit with the vertex's normal if there is one per vertex
+    fn draw_points(&mut self, renderer: &mut Renderer, world: &World) {
+        let Some(primitive) = self.primitives.first_mut() else {
+            return;
+        };
+        let origin = Vector2::new(0., 0.);
+        // Normals of .obj files aren't stored per vertex
+        let per_vertex_normals = self.normals.len() == self.vertices.len();
+        for (i, vertex) in self.vertices.iter().enumerate() {
+            let normal = match per_vertex_normals {
+                true => self.transformation.rotated(&self.normals[i]),
+                false => Vector3::new(0., 0., 0.),
+            };
+            let mut attributes = Attributes {
+                uv: self.uvs.get(i).copied().unwrap_or(origin),
+                color: self
+                    .colors
+                    .get(i)
+                    .copied()
+                    .unwrap_or(Color::rgb(255, 255, 255)),
+                normal,
+                position: self.transformation.transformed(vertex),
+                ..Default::default()
+            };
+            let position = primitive.shader.vertex(&mut attributes, world);
+            if !clipping::point_visible(&position) {
+                continue;
             }
+            let brightness = match per_vertex_normals {
+                true => primitive
+                    .shader
+                    .calculate_lighting(&attributes.normal, world),
+                false => 1.,
+            };
+            // Points don't have a size on the screen, so their attributes don't change
+            let color = primitive.shader.fragment(
+                &Vector3::new(1., 0., 0.),
+                &Derivatives::zero(),
+                &[attributes; 3],
+            ) * brightness;
+            renderer.draw_point(&world.camera.to_ndc(position), color);
         }
     }
 }
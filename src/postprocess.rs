@@ -0,0 +1,87 @@
+use crate::graphics::Color;
+
+/// Minimum local contrast for a pixel to be considered on an edge
+const EDGE_THRESHOLD: f32 = 0.125;
+/// Contrast under which dark areas are left alone
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+/// How much aliasing on single pixels is smoothed (0 to 1)
+const SUBPIXEL_QUALITY: f32 = 0.75;
+
+/// Perceived brightness of a packed 0x00RRGGBB pixel, from 0 to 1
+fn luma(pixel: u32) -> f32 {
+    let color = Color::from(pixel);
+    (0.299 * color.0 as f32 + 0.587 * color.1 as f32 + 0.114 * color.2 as f32) / 255.
+}
+
+/// FXAA-style anti-aliasing on a packed 0x00RRGGBB image. Edges are found from the contrast in
+/// luma, and pixels on them are blended with their neighbour across the edge depending on how
+/// much they stand out. Unlike full FXAA there is no search for the ends of edges, which makes it
+/// cheaper but less effective on long, nearly horizontal/vertical edges.
+/// The result is written to `target`, which must be the same size as `source`.
+pub fn fxaa(source: &[u32], target: &mut [u32], width: u32, height: u32) {
+    target.copy_from_slice(source);
+    let (width, height) = (width as i32, height as i32);
+    let at =
+        |x: i32, y: i32| source[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = luma(at(x, y));
+            let north = luma(at(x, y - 1));
+            let south = luma(at(x, y + 1));
+            let east = luma(at(x + 1, y));
+            let west = luma(at(x - 1, y));
+            let max = center.max(north).max(south).max(east).max(west);
+            let min = center.min(north).min(south).min(east).min(west);
+            let range = max - min;
+            if range < EDGE_THRESHOLD_MIN.max(max * EDGE_THRESHOLD) {
+                continue;
+            }
+
+            let north_west = luma(at(x - 1, y - 1));
+            let north_east = luma(at(x + 1, y - 1));
+            let south_west = luma(at(x - 1, y + 1));
+            let south_east = luma(at(x + 1, y + 1));
+
+            // Is the edge horizontal or vertical?
+            let horizontal = (north + south - 2. * center).abs() * 2.
+                + (north_west + south_west - 2. * west).abs()
+                + (north_east + south_east - 2. * east).abs();
+            let vertical = (west + east - 2. * center).abs() * 2.
+                + (north_west + north_east - 2. * north).abs()
+                + (south_west + south_east - 2. * south).abs();
+            // Blend towards the side of the edge with the steepest gradient
+            let (neighbour_x, neighbour_y) = if horizontal >= vertical {
+                if (north - center).abs() >= (south - center).abs() {
+                    (x, y - 1)
+                } else {
+                    (x, y + 1)
+                }
+            } else if (west - center).abs() >= (east - center).abs() {
+                (x - 1, y)
+            } else {
+                (x + 1, y)
+            };
+
+            let average = (2. * (north + south + east + west)
+                + north_west
+                + north_east
+                + south_west
+                + south_east)
+                / 12.;
+            let subpixel = ((average - center).abs() / range).clamp(0., 1.);
+            let smoothed = subpixel * subpixel * (3. - 2. * subpixel);
+            let blend = smoothed * smoothed * SUBPIXEL_QUALITY;
+
+            let color = Color::from(at(x, y));
+            let neighbour = Color::from(at(neighbour_x, neighbour_y));
+            let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * blend).round() as u8;
+            target[(y * width + x) as usize] = Color::rgb(
+                mix(color.0, neighbour.0),
+                mix(color.1, neighbour.1),
+                mix(color.2, neighbour.2),
+            )
+            .into();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/// Events a presenter can report back to the renderer.
+pub enum Event {
+    Quit,
+    Resized(u32, u32),
+}
+
+/// Something that can show the contents of the renderer's color buffer (a window, a video
+/// encoder...). The renderer itself never needs one: without a presenter frames only live in
+/// memory.
+pub trait Presenter {
+    /// Shows a frame. `pixels` is row-major, `width * height` long and packed as 0x00RRGGBB.
+    fn present(&mut self, pixels: &[u32], width: u32, height: u32);
+    /// Returns the events that happened since the last call.
+    fn poll_events(&mut self) -> Vec<Event>;
+}
+
+/// Presents frames in an SDL2 window. Frames are uploaded once per present to a streaming
+/// texture, which is then copied to the window.
+#[cfg(feature = "sdl")]
+pub struct SdlPresenter {
+    canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    event_pump: sdl2::EventPump,
+    texture_creator: sdl2::render::TextureCreator<sdl2::video::WindowContext>,
+    texture: Option<sdl2::render::Texture>,
+    texture_size: (u32, u32),
+}
+
+#[cfg(feature = "sdl")]
+impl SdlPresenter {
+    /// Opens a new resizable window
+    pub fn new(title: &str, width: u32, height: u32) -> SdlPresenter {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+        let window = video_subsystem
+            .window(title, width, height)
+            .position_centered()
+            .resizable()
+            .build()
+            .unwrap();
+        let canvas = window.into_canvas().build().unwrap();
+
+        SdlPresenter {
+            texture_creator: canvas.texture_creator(),
+            canvas,
+            event_pump: sdl_context.event_pump().unwrap(),
+            texture: None,
+            texture_size: (0, 0),
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl Presenter for SdlPresenter {
+    fn present(&mut self, pixels: &[u32], width: u32, height: u32) {
+        // (Re)create the texture when the frame size changes
+        if self.texture.is_none() || self.texture_size != (width, height) {
+            let texture = self
+                .texture_creator
+                .create_texture_streaming(sdl2::pixels::PixelFormatEnum::RGB888, width, height)
+                .expect("can't create the streaming texture");
+            if let Some(old_texture) = self.texture.replace(texture) {
+                // Safe: the old texture isn't used anymore and its renderer is still alive
+                unsafe { old_texture.destroy() };
+            }
+            self.texture_size = (width, height);
+        }
+        let texture = self.texture.as_mut().unwrap();
+        texture
+            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for (y, row) in pixels.chunks_exact(width as usize).enumerate() {
+                    let line = &mut buffer[y * pitch..y * pitch + width as usize * 4];
+                    for (destination, pixel) in line.chunks_exact_mut(4).zip(row) {
+                        destination.copy_from_slice(&pixel.to_ne_bytes());
+                    }
+                }
+            })
+            .expect(":(");
+        self.canvas.copy(texture, None, None).expect(":(");
+        self.canvas.present();
+        ::std::thread::sleep(std::time::Duration::new(0, 1_000_000_000u32 / 75));
+    }
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        self.event_pump
+            .poll_iter()
+            .filter_map(|event| match event {
+                sdl2::event::Event::Quit { .. } => Some(Event::Quit),
+                sdl2::event::Event::Window {
+                    win_event: sdl2::event::WindowEvent::Resized(width, height),
+                    ..
+                } => Some(Event::Resized(width as u32, height as u32)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl Drop for SdlPresenter {
+    fn drop(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            // Safe: the canvas (and its renderer) are dropped after this
+            unsafe { texture.destroy() };
+        }
+    }
+}
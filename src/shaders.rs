@@ -1,94 +1,242 @@
+use std::{path::Path, sync::Arc};
+
 use crate::{
-    graphics::Color,
-    math::{Vector2, Vector3},
+    graphics::{AlphaMode, Attributes, Color, Derivatives, Lighting},
+    loader::LoadError,
+    math::{Vector3, Vector4},
+    texture::{Sampler, Texture, Wrap},
     world::World,
 };
 
-use image::{flat::Error, io::Reader as ImageReader};
-
 // Generic trait for all shaders.
-pub trait Shader {
+// The vertex stage runs on every corner of a face, with its attributes in world space. It returns
+// the corner's position in clip space and can change the attributes (positions, normals, custom
+// varyings...) that get interpolated across the face.
+// The barycentric coordinates given to fragment are already perspective corrected (unless the
+// renderer uses affine interpolation), so the attributes of the vertices (UVs, colors, normals)
+// can be interpolated directly with them (see Attributes::interpolate).
+// Shaders are shared between the threads that rasterize tiles, so they need to be Sync.
+pub trait Shader: Send + Sync {
+    // by default, projects the vertex with the camera as it is
+    fn vertex(&self, attributes: &mut Attributes, world: &World) -> Vector4 {
+        world.camera.project(&attributes.position)
+    }
     fn fragment(
         &self,
         barycentric: &Vector3,
-        uva: (&Vector2, f32),
-        uvb: (&Vector2, f32),
-        uvc: (&Vector2, f32),
+        derivatives: &Derivatives,
+        attributes: &[Attributes; 3],
     ) -> Color;
     // by default, calculates lighting but does not apply it in the fragment shader (unshaded)
     fn calculate_lighting(&mut self, normal: &Vector3, world: &World) -> f32 {
-        1. - (normal.cos_similarity(&world.light.direction) * world.light.intensity + world.ambient)
+        diffuse(normal, world)
+    }
+    // highlights of the surface, none by default
+    fn specular(&self) -> Specular {
+        Specular::new(Color::rgb(0, 0, 0), 0.)
+    }
+    // how the alpha of the fragments is used, ignored by default
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Opaque
+    }
+    // lights the color of a fragment with its interpolated normal and position when lighting is
+    // done per pixel: diffuse like calculate_lighting, plus specular highlights
+    fn light_fragment(
+        &self,
+        color: Color,
+        attributes: &Attributes,
+        world: &World,
+        lighting: Lighting,
+    ) -> Color {
+        let normal = attributes.normal.normalised();
+        let lit = color * diffuse(&normal, world);
+        let specular = self.specular();
+        // Direction to the light
+        let light = (world.light.direction * -1.).normalised();
+        if specular.color == Color::rgb(0, 0, 0)
+            || specular.exponent <= 0.
+            || normal.dot_product(&light) <= 0.
+        {
+            return lit;
+        }
+        let view = (world.camera.position - attributes.position).normalised();
+        let alignment = match lighting {
+            Lighting::BlinnPhong => normal.dot_product(&(light + view).normalised()),
+            _ => {
+                let reflected = normal * (2. * normal.dot_product(&light)) - light;
+                reflected.dot_product(&view)
+            }
+        };
+        let highlight = alignment.max(0.).powf(specular.exponent) * world.light.intensity;
+        if !highlight.is_finite() {
+            return lit;
+        }
+        lit + specular.color * highlight
+    }
+}
+
+// Diffuse lighting of a surface, as a factor of its color
+fn diffuse(normal: &Vector3, world: &World) -> f32 {
+    1. - (normal.cos_similarity(&world.light.direction) * world.light.intensity + world.ambient)
+}
+
+/// Specular highlights of a surface, for per pixel lighting. Surfaces with a black color or an
+/// exponent of 0 (the default of .mtl files) don't have any.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Specular {
+    pub color: Color,
+    /// Higher exponents give smaller, sharper highlights
+    pub exponent: f32,
+}
+
+impl Specular {
+    pub fn new(color: Color, exponent: f32) -> Specular {
+        Specular { color, exponent }
     }
 }
 
 // "Standard material" -- shading, colors
 pub struct StandardShader {
     pub color: Color,
+    pub specular: Specular,
+    pub alpha_mode: AlphaMode,
 }
 
 impl Shader for StandardShader {
     fn fragment(
         &self,
         _barycentric: &Vector3,
-        _uva: (&Vector2, f32),
-        _uvb: (&Vector2, f32),
-        _uvc: (&Vector2, f32),
+        _derivatives: &Derivatives,
+        _attributes: &[Attributes; 3],
     ) -> Color {
         self.color
     }
+    fn specular(&self) -> Specular {
+        self.specular
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
 }
 
 impl StandardShader {
+    /// Creates an opaque shader without specular highlights
     pub fn new(color: Color) -> StandardShader {
-        StandardShader { color }
+        StandardShader {
+            color,
+            specular: Specular::new(Color::rgb(0, 0, 0), 0.),
+            alpha_mode: AlphaMode::Opaque,
+        }
     }
 }
 
 // "Texture material" -- shading, textures
 pub struct TextureShader {
-    image: Vec<Color>,
-    width: f32,
-    height: f32,
+    /// Shared with the materials (and other shaders) that use it
+    pub texture: Arc<Texture>,
+    pub sampler: Sampler,
+    /// Multiplied with the texture (white by default)
+    pub color: Color,
+    /// Multiplies the alpha of the texture: with the alpha of the map if it has one, with its gray
+    /// level otherwise (map_d in .mtl files). Sampled like the texture.
+    pub alpha_map: Option<Arc<Texture>>,
+    pub specular: Specular,
+    pub alpha_mode: AlphaMode,
 }
 
 impl Shader for TextureShader {
     fn fragment(
         &self,
         barycentric: &Vector3,
-        uva: (&Vector2, f32),
-        uvb: (&Vector2, f32),
-        uvc: (&Vector2, f32),
+        derivatives: &Derivatives,
+        attributes: &[Attributes; 3],
     ) -> Color {
-        // TODO: perspective correction?
-        let uvx = barycentric.x * uva.0.x + barycentric.y * uvb.0.x + barycentric.z * uvc.0.x;
-        let uvy = barycentric.x * uva.0.y + barycentric.y * uvb.0.y + barycentric.z * uvc.0.y;
-
-        // convert to texture space
-        let tx = (uvx.min(1.) * self.width) as usize;
-        let ty = (uvy.min(1.) * self.height) as usize;
-
-        // grab the corresponding pixel color on the texture
-        self.image[tx + (ty * self.width as usize)]
+        let uv = Attributes::interpolate(barycentric, attributes).uv;
+        let [dx, dy] = Attributes::uv_derivatives(derivatives, attributes);
+        let color = self.texture.sample_gradient(&uv, &dx, &dy, &self.sampler) * self.color;
+        let Some(alpha_map) = &self.alpha_map else {
+            return color;
+        };
+        let coverage = alpha_map.sample_gradient(&uv, &dx, &dy, &self.sampler);
+        let coverage = match alpha_map.has_alpha() {
+            true => coverage.3,
+            false => coverage.0,
+        };
+        color * Color(255, 255, 255, coverage)
+    }
+    fn specular(&self) -> Specular {
+        self.specular
+    }
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
     }
 }
 
 impl TextureShader {
-    pub fn new(image: Vec<Color>, width: f32, height: f32) -> TextureShader {
+    /// Creates an opaque shader without specular highlights, that repeats the texture with
+    /// trilinear filtering. Mipmaps are generated for the texture.
+    pub fn new(mut texture: Texture) -> TextureShader {
+        texture.generate_mipmaps();
+        TextureShader::shared(Arc::new(texture))
+    }
+    /// Like new, for a texture that is already shared. Its mipmaps are used as they are.
+    pub fn shared(texture: Arc<Texture>) -> TextureShader {
         TextureShader {
-            image,
-            width,
-            height,
+            texture,
+            sampler: Sampler::trilinear(Wrap::Repeat),
+            color: Color::rgb(255, 255, 255),
+            alpha_map: None,
+            specular: Specular::new(Color::rgb(0, 0, 0), 0.),
+            alpha_mode: AlphaMode::Opaque,
         }
     }
-    pub fn image2vec(path: &str) -> Result<Vec<Color>, std::io::Error> {
-        let image = ImageReader::open(path)?.decode();
-        let mut vector = Vec::new();
-        if image.is_ok() {
-            for pixel in image.unwrap().to_rgb8().pixels() {
-                vector.push(Color(pixel.0[0], pixel.0[1], pixel.0[2]));
-            }
-        }
-        Ok(vector)
+    /// Loads an image file, using its own size
+    pub fn load(path: &Path) -> Result<TextureShader, LoadError> {
+        Ok(TextureShader::new(Texture::load(path)?))
+    }
+}
+
+// "Vertex color material" -- shading, colors interpolated between the vertices
+pub struct VertexColorShader;
+
+impl Shader for VertexColorShader {
+    fn fragment(
+        &self,
+        barycentric: &Vector3,
+        _derivatives: &Derivatives,
+        attributes: &[Attributes; 3],
+    ) -> Color {
+        Attributes::interpolate(barycentric, attributes).color
+    }
+}
+
+// Normal shader -- no shading, colors from the interpolated normals (x, y, z as red, green, blue)
+pub struct NormalShader;
+
+impl Shader for NormalShader {
+    fn fragment(
+        &self,
+        barycentric: &Vector3,
+        _derivatives: &Derivatives,
+        attributes: &[Attributes; 3],
+    ) -> Color {
+        let normal = Attributes::interpolate(barycentric, attributes)
+            .normal
+            .normalised();
+        let channel = |x: f32| ((x + 1.) * 127.5).clamp(0., 255.) as u8;
+        Color::rgb(channel(normal.x), channel(normal.y), channel(normal.z))
+    }
+    fn calculate_lighting(&mut self, _normal: &Vector3, _world: &World) -> f32 {
+        1.
+    }
+    fn light_fragment(
+        &self,
+        color: Color,
+        _attributes: &Attributes,
+        _world: &World,
+        _lighting: Lighting,
+    ) -> Color {
+        color
     }
 }
 
@@ -102,9 +250,8 @@ impl Shader for WireframeShader {
     fn fragment(
         &self,
         barycentric: &Vector3,
-        _uva: (&Vector2, f32),
-        _uvb: (&Vector2, f32),
-        _uvc: (&Vector2, f32),
+        _derivatives: &Derivatives,
+        _attributes: &[Attributes; 3],
     ) -> Color {
         if barycentric.x <= self.thickness
             || barycentric.y <= self.thickness
@@ -112,12 +259,21 @@ impl Shader for WireframeShader {
         {
             self.color
         } else {
-            Color(0, 0, 0)
+            Color::rgb(0, 0, 0)
         }
     }
     fn calculate_lighting(&mut self, _normal: &Vector3, _world: &World) -> f32 {
         1.
     }
+    fn light_fragment(
+        &self,
+        color: Color,
+        _attributes: &Attributes,
+        _world: &World,
+        _lighting: Lighting,
+    ) -> Color {
+        color
+    }
 }
 
 impl WireframeShader {
@@ -0,0 +1,468 @@
+use std::{fmt, path::Path};
+
+use image::{
+    error::{ParameterError, ParameterErrorKind},
+    ColorType, DynamicImage, ImageError, ImageReader, ImageResult, RgbaImage,
+};
+
+use crate::{
+    graphics::Color,
+    loader::{LoadError, LoadErrorKind},
+    math::Vector2,
+};
+
+/// How texels are picked for a UV
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Filter {
+    /// The texel the UV is in
+    Nearest,
+    /// The four texels around the UV, weighted by how close their centers are
+    Bilinear,
+}
+
+/// What happens to UVs outside of 0..1
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Wrap {
+    /// The texture is tiled
+    Repeat,
+    /// The texture is tiled, every other tile being flipped
+    MirroredRepeat,
+    /// The texels on the edges are stretched
+    ClampToEdge,
+}
+
+impl Wrap {
+    /// Brings a texel coordinate back into 0..size
+    fn apply(self, coordinate: i64, size: i64) -> usize {
+        (match self {
+            Wrap::Repeat => coordinate.rem_euclid(size),
+            Wrap::MirroredRepeat => {
+                let coordinate = coordinate.rem_euclid(2 * size);
+                if coordinate < size {
+                    coordinate
+                } else {
+                    2 * size - 1 - coordinate
+                }
+            }
+            Wrap::ClampToEdge => coordinate.clamp(0, size - 1),
+        }) as usize
+    }
+}
+
+/// How textures are sampled between their mipmaps (smaller copies of the texture)
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mipmaps {
+    /// Only the full size texture is used
+    Off,
+    /// The closest mipmap to the size the texture is drawn at
+    Nearest,
+    /// The two closest mipmaps, blended together (trilinear filtering with bilinear)
+    Linear,
+}
+
+/// How a texture is read
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sampler {
+    /// Filtering within a mipmap
+    pub filter: Filter,
+    pub mipmaps: Mipmaps,
+    /// Largest number of samples taken along a surface seen at a grazing angle (anisotropic
+    /// filtering), 1 to only take one
+    pub anisotropy: u32,
+    /// Wrapping of u, horizontally
+    pub wrap_u: Wrap,
+    /// Wrapping of v, vertically
+    pub wrap_v: Wrap,
+}
+
+impl Sampler {
+    /// Creates a sampler that wraps u and v the same way, without mipmaps
+    pub fn new(filter: Filter, wrap: Wrap) -> Sampler {
+        Sampler {
+            filter,
+            mipmaps: Mipmaps::Off,
+            anisotropy: 1,
+            wrap_u: wrap,
+            wrap_v: wrap,
+        }
+    }
+
+    /// Creates a sampler with bilinear filtering between the two closest mipmaps
+    pub fn trilinear(wrap: Wrap) -> Sampler {
+        Sampler {
+            mipmaps: Mipmaps::Linear,
+            ..Sampler::new(Filter::Bilinear, wrap)
+        }
+    }
+}
+
+/// How the values of a texture's channels relate to light
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ColorSpace {
+    /// Gamma encoded, like most images meant to be looked at (colors)
+    Srgb,
+    /// Proportional to light, or not colors at all (normal maps...)
+    Linear,
+}
+
+impl ColorSpace {
+    /// Converts a channel from the color space to linear, from 0 to 1
+    fn decode(self, channel: u8) -> f32 {
+        let channel = channel as f32 / 255.;
+        match self {
+            ColorSpace::Linear => channel,
+            ColorSpace::Srgb if channel <= 0.04045 => channel / 12.92,
+            ColorSpace::Srgb => ((channel + 0.055) / 1.055).powf(2.4),
+        }
+    }
+
+    /// Converts a linear channel (from 0 to 1) to the color space
+    fn encode(self, channel: f32) -> u8 {
+        let channel = match self {
+            ColorSpace::Linear => channel,
+            ColorSpace::Srgb if channel <= 0.0031308 => channel * 12.92,
+            ColorSpace::Srgb => 1.055 * channel.powf(1. / 2.4) - 0.055,
+        };
+        (channel.clamp(0., 1.) * 255.).round() as u8
+    }
+}
+
+/// Red, green, blue and alpha
+type Texel = [u8; 4];
+
+/// An image that shaders can sample. UVs start at the top left of the image.
+/// Texels are stored with 8 bits per channel, as RGBA, whatever the channels of the image were.
+/// Textures can have mipmaps, each one half the size of the previous one.
+#[derive(Clone)]
+pub struct Texture {
+    /// The texture, then its mipmaps
+    levels: Vec<Level>,
+    channels: usize,
+    color_space: ColorSpace,
+}
+
+#[derive(Clone)]
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<Texel>,
+}
+
+impl Texture {
+    /// Creates an sRGB texture from its pixels, row by row. It has an alpha channel if any of the
+    /// pixels isn't opaque. Fails if there aren't exactly width * height pixels.
+    pub fn new(pixels: Vec<Color>, width: usize, height: usize) -> ImageResult<Texture> {
+        if pixels.len() != width * height {
+            let kind = ParameterErrorKind::DimensionMismatch;
+            return Err(ImageError::Parameter(ParameterError::from_kind(kind)));
+        }
+        let texels: Vec<Texel> = pixels
+            .into_iter()
+            .map(|Color(r, g, b, a)| [r, g, b, a])
+            .collect();
+        let opaque = texels.iter().all(|texel| texel[3] == 255);
+        Ok(Texture {
+            levels: vec![Level {
+                width,
+                height,
+                texels,
+            }],
+            channels: if opaque { 3 } else { 4 },
+            color_space: ColorSpace::Srgb,
+        })
+    }
+
+    /// Creates a texture from an image of any format. Grayscale images are stored as gray colors,
+    /// and images without alpha are opaque. Channels with more than 8 bits are rounded.
+    pub fn from_image(image: &DynamicImage, color_space: ColorSpace) -> Texture {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        Texture {
+            levels: vec![Level {
+                width,
+                height,
+                texels: image.to_rgba8().pixels().map(|pixel| pixel.0).collect(),
+            }],
+            channels: image.color().channel_count() as usize,
+            color_space,
+        }
+    }
+
+    /// Loads an image file, as sRGB unless its channels are floats (HDR images)
+    pub fn load(path: &Path) -> Result<Texture, LoadError> {
+        let image = ImageReader::open(path)
+            .map_err(|error| LoadError::new(path, None, LoadErrorKind::MissingTexture(error)))?
+            .decode()
+            .map_err(|error| LoadError::new(path, None, LoadErrorKind::InvalidTexture(error)))?;
+        let color_space = match image.color() {
+            ColorType::Rgb32F | ColorType::Rgba32F => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        };
+        Ok(Texture::from_image(&image, color_space))
+    }
+
+    /// Saves the full size texture to an image file, with its alpha if it has any. The format is
+    /// deduced from the extension (.png, .ppm, ...).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        let level = &self.levels[0];
+        let texels = level.texels.iter().flatten().copied().collect();
+        let image: DynamicImage =
+            RgbaImage::from_raw(level.width as u32, level.height as u32, texels)
+                .expect("texels don't match the texture size")
+                .into();
+        if self.has_alpha() {
+            image.save(path)
+        } else {
+            image.to_rgb8().save(path)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// Number of channels of the image the texture was made from: 1 (gray), 2 (gray and alpha),
+    /// 3 (RGB) or 4 (RGBA)
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.channels == 2 || self.channels == 4
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Changes how the texels are interpreted, for images that hold data rather than colors (alpha
+    /// maps...). Mipmaps need to be generated again afterwards.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    /// Number of mipmaps, including the full size texture
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Size of a mipmap (0 being the full size texture)
+    pub fn level_size(&self, level: usize) -> (usize, usize) {
+        let level = &self.levels[level.min(self.levels.len() - 1)];
+        (level.width, level.height)
+    }
+
+    /// (Re)generates the mipmaps, down to 1x1. Every texel of a mipmap is the average of the
+    /// texels it covers in the previous one (in linear space, for sRGB textures). Colors are
+    /// weighted by their alpha, so that transparent texels don't bleed into the opaque ones.
+    pub fn generate_mipmaps(&mut self) {
+        self.levels.truncate(1);
+        let color_space = self.color_space;
+        let decode: Vec<f32> = (0..=255)
+            .map(|channel| color_space.decode(channel))
+            .collect();
+        loop {
+            let previous = self.levels.last().unwrap();
+            // Empty textures (0 wide or high) don't have anything to average
+            if previous.texels.is_empty() || (previous.width <= 1 && previous.height <= 1) {
+                break;
+            }
+            let (width, height) = ((previous.width / 2).max(1), (previous.height / 2).max(1));
+            // Range of texels of the previous level covered by a texel of this one
+            let covered = |x: usize, size: usize, previous: usize| {
+                let start = x * previous / size;
+                start..((x + 1) * previous / size).max(start + 1)
+            };
+            let mut texels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    // Premultiplied by alpha, then not, for when everything is transparent
+                    let mut sum = [0.; 4];
+                    let mut unweighted = [0.; 3];
+                    let mut count = 0.;
+                    for y in covered(y, height, previous.height) {
+                        for x in covered(x, width, previous.width) {
+                            let texel = previous.texels[x + y * previous.width];
+                            // Alpha is always linear
+                            let alpha = texel[3] as f32 / 255.;
+                            for i in 0..3 {
+                                sum[i] += decode[texel[i] as usize] * alpha;
+                                unweighted[i] += decode[texel[i] as usize];
+                            }
+                            sum[3] += alpha;
+                            count += 1.;
+                        }
+                    }
+                    let channel = |i: usize| {
+                        if sum[3] > 0. {
+                            color_space.encode(sum[i] / sum[3])
+                        } else {
+                            color_space.encode(unweighted[i] / count)
+                        }
+                    };
+                    texels.push([
+                        channel(0),
+                        channel(1),
+                        channel(2),
+                        (sum[3] / count * 255.).round() as u8,
+                    ]);
+                }
+            }
+            self.levels.push(Level {
+                width,
+                height,
+                texels,
+            });
+        }
+    }
+
+    /// Returns the pixel at (x, y), wrapped with the sampler
+    pub fn texel(&self, x: i64, y: i64, sampler: &Sampler) -> Color {
+        color(self.levels[0].texel(x, y, sampler))
+    }
+
+    /// Returns the color of the full size texture at a UV
+    pub fn sample(&self, uv: &Vector2, sampler: &Sampler) -> Color {
+        color(self.levels[0].sample(uv, sampler))
+    }
+
+    /// Returns the color of the texture at a UV, given how much the UV changes from one pixel to
+    /// the next one horizontally (`dx`) and vertically (`dy`). The more texels a pixel covers, the
+    /// smaller the mipmap it is sampled from.
+    pub fn sample_gradient(
+        &self,
+        uv: &Vector2,
+        dx: &Vector2,
+        dy: &Vector2,
+        sampler: &Sampler,
+    ) -> Color {
+        color(self.sample_footprint(uv, dx, dy, sampler))
+    }
+
+    fn sample_footprint(
+        &self,
+        uv: &Vector2,
+        dx: &Vector2,
+        dy: &Vector2,
+        sampler: &Sampler,
+    ) -> Texel {
+        if sampler.mipmaps == Mipmaps::Off && sampler.anisotropy <= 1 {
+            return self.levels[0].sample(uv, sampler);
+        }
+        // Footprint of the pixel, in texels
+        let size = Vector2::new(self.width() as f32, self.height() as f32);
+        let [dx, dy] = [dx, dy].map(|d| Vector2::new(d.x * size.x, d.y * size.y));
+        let [length_x, length_y] = [dx, dy].map(|d| (d.x * d.x + d.y * d.y).sqrt());
+        let (major, axis, minor) = if length_x >= length_y {
+            (length_x, dx, length_y)
+        } else {
+            (length_y, dy, length_x)
+        };
+        // Anisotropic filtering takes several samples along the longest side of the footprint,
+        // which can then be sampled from a mipmap as big as its shortest side
+        let samples = (major / minor.max(f32::EPSILON))
+            .min(sampler.anisotropy.max(1) as f32)
+            .ceil()
+            .max(1.);
+        let lod = (major / samples).log2();
+        if !lod.is_finite() {
+            return self.levels[0].sample(uv, sampler);
+        }
+
+        let count = samples as usize;
+        let mut sum = [0.; 4];
+        for i in 0..count {
+            let offset = (i as f32 + 0.5) / samples - 0.5;
+            let uv = Vector2::new(
+                uv.x + axis.x * offset / size.x,
+                uv.y + axis.y * offset / size.y,
+            );
+            let texel = self.sample_level(&uv, lod, sampler);
+            for (sum, channel) in sum.iter_mut().zip(texel) {
+                *sum += channel as f32;
+            }
+        }
+        sum.map(|channel| (channel / samples).round() as u8)
+    }
+
+    /// Samples the mipmaps at a level of detail (log2 of the number of texels per pixel)
+    fn sample_level(&self, uv: &Vector2, lod: f32, sampler: &Sampler) -> Texel {
+        let last = (self.levels.len() - 1) as f32;
+        let lod = lod.clamp(0., last);
+        match sampler.mipmaps {
+            Mipmaps::Off => self.levels[0].sample(uv, sampler),
+            Mipmaps::Nearest => self.levels[lod.round() as usize].sample(uv, sampler),
+            Mipmaps::Linear => {
+                let level = lod.floor();
+                let a = self.levels[level as usize].sample(uv, sampler);
+                if level >= last {
+                    return a;
+                }
+                let b = self.levels[level as usize + 1].sample(uv, sampler);
+                mix(a, b, lod - level)
+            }
+        }
+    }
+}
+
+// The texels would drown everything else
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Texture")
+            .field("width", &self.width())
+            .field("height", &self.height())
+            .field("levels", &self.levels.len())
+            .field("channels", &self.channels)
+            .field("color_space", &self.color_space)
+            .finish()
+    }
+}
+
+impl Level {
+    fn texel(&self, x: i64, y: i64, sampler: &Sampler) -> Texel {
+        if self.texels.is_empty() {
+            return [0, 0, 0, 255];
+        }
+        let x = sampler.wrap_u.apply(x, self.width as i64);
+        let y = sampler.wrap_v.apply(y, self.height as i64);
+        self.texels[x + y * self.width]
+    }
+
+    fn sample(&self, uv: &Vector2, sampler: &Sampler) -> Texel {
+        // Texture space, where the center of a texel is at +0.5
+        let x = uv.x * self.width as f32;
+        let y = uv.y * self.height as f32;
+        if !(x.is_finite() && y.is_finite()) {
+            return [0, 0, 0, 255];
+        }
+        match sampler.filter {
+            Filter::Nearest => self.texel(x.floor() as i64, y.floor() as i64, sampler),
+            Filter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (left, top) = (x.floor(), y.floor());
+                let (tx, ty) = (x - left, y - top);
+                let (left, top) = (left as i64, top as i64);
+                // Blend horizontally on the two rows, then vertically
+                let row = |y: i64| {
+                    let a = self.texel(left, y, sampler);
+                    let b = self.texel(left + 1, y, sampler);
+                    [0, 1, 2, 3].map(|i| a[i] as f32 + (b[i] as f32 - a[i] as f32) * tx)
+                };
+                let (top, bottom) = (row(top), row(top + 1));
+                [0, 1, 2, 3].map(|i| (top[i] + (bottom[i] - top[i]) * ty).round() as u8)
+            }
+        }
+    }
+}
+
+/// Linearly interpolates between two texels
+fn mix(a: Texel, b: Texel, t: f32) -> Texel {
+    [0, 1, 2, 3].map(|i| (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8)
+}
+
+fn color([r, g, b, a]: Texel) -> Color {
+    Color(r, g, b, a)
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use renderer::{
+    graphics::Color,
+    loader,
+    texture::{Filter, Sampler, Texture, Wrap},
+};
+
+#[test]
+fn stl_round_trip() {
+    let mesh = loader::load("models/suzanne.obj").unwrap();
+    let path = std::env::temp_dir().join("renderer-test-suzanne.stl");
+    mesh.write_stl(&path).unwrap();
+    let loaded = loader::load_stl(path.to_str().unwrap()).unwrap();
+
+    // Suzanne has a few vertices at the same position, which get welded
+    assert!(loaded.vertices.len() < mesh.vertices.len());
+    assert_eq!(loaded.faces.len(), mesh.faces.len());
+    for (face, loaded_face) in mesh.faces.iter().zip(&loaded.faces) {
+        assert!(mesh.vertices[face.a] == loaded.vertices[loaded_face.a]);
+        assert!(mesh.vertices[face.b] == loaded.vertices[loaded_face.b]);
+        assert!(mesh.vertices[face.c] == loaded.vertices[loaded_face.c]);
+    }
+}
+
+#[test]
+fn obj_round_trip() {
+    let mesh = loader::load("models/quad_damage/quad_damage.obj").unwrap();
+    let path = std::env::temp_dir().join("renderer-test-quad_damage.obj");
+    mesh.write_obj(&path).unwrap();
+    let loaded = loader::load(path.to_str().unwrap()).unwrap();
+
+    assert!(loaded.vertices == mesh.vertices);
+    assert!(loaded.normals == mesh.normals);
+    assert_eq!(loaded.uvs.len(), mesh.uvs.len());
+    for (uv, loaded_uv) in mesh.uvs.iter().zip(&loaded.uvs) {
+        assert!((uv.x - loaded_uv.x).abs() < 1e-6 && (uv.y - loaded_uv.y).abs() < 1e-6);
+    }
+    assert_eq!(loaded.faces.len(), mesh.faces.len());
+    for (face, loaded_face) in mesh.faces.iter().zip(&loaded.faces) {
+        assert_eq!(
+            (face.a, face.b, face.c, face.uva, face.uvb, face.uvc),
+            (
+                loaded_face.a,
+                loaded_face.b,
+                loaded_face.c,
+                loaded_face.uva,
+                loaded_face.uvb,
+                loaded_face.uvc
+            )
+        );
+        assert_eq!(
+            (face.na, face.nb, face.nc),
+            (loaded_face.na, loaded_face.nb, loaded_face.nc)
+        );
+        assert_eq!(face.smoothing_group, loaded_face.smoothing_group);
+    }
+
+    // The material (and its texture) come along
+    assert_eq!(loaded.materials.len(), 1);
+    let (material, loaded_material) = (&mesh.materials[0], &loaded.materials[0]);
+    assert_eq!(loaded_material.name, material.name);
+    assert_eq!(loaded_material.specular, material.specular);
+    assert_eq!(
+        std::fs::canonicalize(loaded_material.diffuse_map.as_ref().unwrap()).unwrap(),
+        std::fs::canonicalize(material.diffuse_map.as_ref().unwrap()).unwrap()
+    );
+    assert_eq!(loaded.primitives[0].material, Some(0));
+    let groups = |mesh: &renderer::mesh::Mesh| -> Vec<_> {
+        mesh.groups
+            .iter()
+            .map(|group| (group.name.clone(), group.faces.clone()))
+            .collect()
+    };
+    assert_eq!(groups(&loaded), groups(&mesh));
+}
+
+#[test]
+fn faces_without_material_get_a_default_one() {
+    let directory = std::env::temp_dir();
+    std::fs::write(
+        directory.join("renderer-test-mixed.mtl"),
+        "newmtl default\nKd 1 0 0\n",
+    )
+    .unwrap();
+    let path = directory.join("renderer-test-mixed.obj");
+    std::fs::write(
+        &path,
+        "mtllib renderer-test-mixed.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\n\
+         usemtl default\nf 1 2 3\nusemtl missing\nf 1 3 2\n",
+    )
+    .unwrap();
+    let mesh = loader::load(path.to_str().unwrap()).unwrap();
+    assert_eq!(mesh.primitives[1].material, None);
+
+    let exported = directory.join("renderer-test-mixed-export.obj");
+    mesh.write_obj(&exported).unwrap();
+    let obj = std::fs::read_to_string(&exported).unwrap();
+    assert!(obj.contains("usemtl default\n") && obj.contains("usemtl default_\n"));
+    let loaded = loader::load(exported.to_str().unwrap()).unwrap();
+    let names: Vec<_> = loaded.materials.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["default", "default_"]);
+    assert_eq!(loaded.materials[1].diffuse, Color::rgb(255, 255, 255));
+    assert_eq!(loaded.primitives[1].material, Some(1));
+}
+
+#[test]
+fn textures_without_a_file_are_saved_next_to_the_mtl() {
+    // Like an image embedded in a glTF file
+    let mut mesh = loader::load("models/quad_damage/quad_damage.obj").unwrap();
+    let pixels = vec![Color::rgb(255, 0, 0), Color(0, 0, 255, 128)];
+    mesh.materials[0].diffuse_map = None;
+    mesh.materials[0].diffuse_texture = Some(Arc::new(Texture::new(pixels, 2, 1).unwrap()));
+    let path = std::env::temp_dir().join("renderer-test-embedded.obj");
+    mesh.write_obj(&path).unwrap();
+
+    let loaded = loader::load(path.to_str().unwrap()).unwrap();
+    let material = &loaded.materials[0];
+    assert_eq!(
+        material.diffuse_map,
+        Some(std::env::temp_dir().join("renderer-test-embedded_0_map_Kd.png"))
+    );
+    let texture = material.diffuse_texture.as_ref().unwrap();
+    let sampler = Sampler::new(Filter::Nearest, Wrap::Repeat);
+    assert_eq!(texture.texel(0, 0, &sampler), Color::rgb(255, 0, 0));
+    assert_eq!(texture.texel(1, 0, &sampler), Color(0, 0, 255, 128));
+}
@@ -0,0 +1,485 @@
+//! Golden-image regression tests. Every model is rendered headlessly with a fixed camera and light
+//! and compared against its reference image in `tests/golden/`. On failure the actual frame and a
+//! diff image are written to `target/golden/`.
+//!
+//! After an intentional change to the output, regenerate the references with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and review them before committing.
+
+use std::path::{Path, PathBuf};
+
+use image::{Rgb, RgbImage};
+use renderer::{
+    camera::PerspectiveCamera,
+    graphics::{
+        AlphaMode, Attributes, Blend, Color, Derivatives, Interpolation, Lighting, Msaa, Renderer,
+    },
+    loader,
+    math::{Vector2, Vector3, Vector4},
+    mesh::{Face, Mesh, Primitive},
+    shaders::{NormalShader, Shader, Specular, StandardShader, TextureShader, VertexColorShader},
+    texture::{Filter, Mipmaps, Sampler, Texture, Wrap},
+    world::{DirectionalLight, World},
+};
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+/// Largest difference on any channel for two pixels to still be considered equal
+const TOLERANCE: u8 = 8;
+/// Fraction of the pixels that may differ (absorbs float noise along edges)
+const MAX_MISMATCHED: f32 = 0.002;
+
+fn world(camera_distance: f32) -> World {
+    let mut world = World {
+        camera: PerspectiveCamera::new(
+            &Vector3::new(0., 0., -camera_distance),
+            WIDTH as f32,
+            HEIGHT as f32,
+        ),
+        light: DirectionalLight::new(&Vector3::new(0., -1., 0.), 0.5),
+        ambient: 0.2,
+    };
+    world.camera.generate_projection_matrix();
+    world
+}
+
+/// Renders a mesh slightly rotated so that three of its sides are visible
+fn render(mesh: &mut Mesh, camera_distance: f32) -> Renderer {
+    render_with(Renderer::headless(WIDTH, HEIGHT), mesh, camera_distance)
+}
+
+/// Same as render, with a renderer that has been configured beforehand
+fn render_with(mut renderer: Renderer, mesh: &mut Mesh, camera_distance: f32) -> Renderer {
+    let world = world(camera_distance);
+    // The rotation of the references from before the matrix fix: it used to be inverted
+    mesh.transformation.quaternion = Vector4::new(-0.2, -0.4, 0., 1.).normalised();
+    mesh.transformation.generate_affine_matrix();
+    renderer.clear();
+    mesh.draw(&mut renderer, &world);
+    renderer.resolve();
+    renderer
+}
+
+fn output_dir() -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Compares the frame against `tests/golden/<name>.png`
+fn check(name: &str, renderer: &mut Renderer) {
+    let reference_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        renderer.save_frame(&reference_path).unwrap();
+        return;
+    }
+
+    let actual = RgbImage::from_raw(WIDTH, HEIGHT, renderer.read_pixels()).unwrap();
+    let expected = image::open(&reference_path)
+        .unwrap_or_else(|_| {
+            panic!(
+                "missing reference {}, run with UPDATE_GOLDEN=1 to create it",
+                reference_path.display()
+            )
+        })
+        .to_rgb8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{}: reference has a different size",
+        name
+    );
+
+    let mut mismatched = 0;
+    let diff = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let distance = (0..3).map(|i| a.0[i].abs_diff(e.0[i])).max().unwrap();
+        if distance > TOLERANCE {
+            mismatched += 1;
+            Rgb([255, 0, 0])
+        } else {
+            Rgb(e.0.map(|channel| channel / 4))
+        }
+    });
+
+    let allowed = (MAX_MISMATCHED * (WIDTH * HEIGHT) as f32) as usize;
+    if mismatched > allowed {
+        let dir = output_dir();
+        actual
+            .save(dir.join(format!("{}.actual.png", name)))
+            .unwrap();
+        diff.save(dir.join(format!("{}.diff.png", name))).unwrap();
+        panic!(
+            "{}: {} pixels differ from the reference (at most {} allowed), see {}",
+            name,
+            mismatched,
+            allowed,
+            dir.display()
+        );
+    }
+}
+
+#[test]
+fn cube() {
+    let mut mesh = loader::load("models/cube.obj").unwrap();
+    check("cube", &mut render(&mut mesh, 3.));
+}
+
+fn quad_damage_mesh() -> Mesh {
+    // The texture comes from the model's material
+    loader::load("models/quad_damage/quad_damage.obj").unwrap()
+}
+
+#[test]
+fn quad_damage() {
+    check("quad_damage", &mut render(&mut quad_damage_mesh(), 2.));
+}
+
+#[test]
+fn quad_damage_affine() {
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.interpolation = Interpolation::Affine;
+    check(
+        "quad_damage_affine",
+        &mut render_with(renderer, &mut quad_damage_mesh(), 2.),
+    );
+}
+
+#[test]
+fn quad_damage_bilinear() {
+    let mut mesh = quad_damage_mesh();
+    let mut shader = TextureShader::load(Path::new("models/quad_damage/texture.png")).unwrap();
+    shader.sampler = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+    mesh.set_shader(Box::new(shader));
+    check("quad_damage_bilinear", &mut render(&mut mesh, 2.));
+}
+
+#[test]
+fn suzanne() {
+    let mut mesh = loader::load("models/suzanne.obj").unwrap();
+    check("suzanne", &mut render(&mut mesh, 3.5));
+}
+
+#[test]
+fn teapot() {
+    let mut mesh = loader::load("models/teapot.obj").unwrap();
+    check("teapot", &mut render(&mut mesh, 5.));
+}
+
+#[test]
+fn tank() {
+    let mut mesh = loader::load("models/tank.obj").unwrap();
+    check("tank", &mut render(&mut mesh, 2.5));
+}
+
+#[test]
+fn tank_near_clipping() {
+    // The camera is inside of the tank: faces crossing the near plane must get clipped
+    let mut mesh = loader::load("models/tank.obj").unwrap();
+    check("tank_near_clipping", &mut render(&mut mesh, 0.5));
+}
+
+#[test]
+fn tiled_rasterization_matches_single_threaded() {
+    let mut mesh = loader::load("models/tank.obj").unwrap();
+    for msaa in [Msaa::Off, Msaa::X4] {
+        let mut single_threaded = Renderer::headless(WIDTH, HEIGHT);
+        single_threaded.threads = 1;
+        single_threaded.set_msaa(msaa);
+        let mut tiled = Renderer::headless(WIDTH, HEIGHT);
+        tiled.threads = 4;
+        tiled.set_msaa(msaa);
+        let single_threaded = render_with(single_threaded, &mut mesh, 2.5);
+        let tiled = render_with(tiled, &mut mesh, 2.5);
+        assert!(single_threaded.color_buffer() == tiled.color_buffer());
+    }
+}
+
+#[test]
+fn cube_msaa() {
+    for (msaa, name) in [
+        (Msaa::X2, "cube_msaa_2x"),
+        (Msaa::X4, "cube_msaa_4x"),
+        (Msaa::X8, "cube_msaa_8x"),
+    ] {
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        renderer.set_msaa(msaa);
+        let mut mesh = loader::load("models/cube.obj").unwrap();
+        check(name, &mut render_with(renderer, &mut mesh, 3.));
+    }
+}
+
+#[test]
+fn cube_fxaa() {
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.fxaa = true;
+    let mut mesh = loader::load("models/cube.obj").unwrap();
+    check("cube_fxaa", &mut render_with(renderer, &mut mesh, 3.));
+}
+
+#[test]
+fn cube_primitives() {
+    let mut mesh = loader::load("models/cube.obj").unwrap();
+    let half = mesh.faces.len() / 2;
+    mesh.primitives = vec![
+        Primitive::new(0..half),
+        Primitive::new(half..mesh.faces.len()),
+    ];
+    mesh.primitives[0].shader = Box::new(StandardShader::new(Color::rgb(255, 64, 64)));
+    mesh.primitives[1].shader = Box::new(StandardShader::new(Color::rgb(64, 64, 255)));
+    check("cube_primitives", &mut render(&mut mesh, 3.));
+}
+
+#[test]
+fn cube_vertex_colors() {
+    let mut mesh = loader::load("models/cube.obj").unwrap();
+    mesh.colors = (0..mesh.vertices.len())
+        .map(|i| {
+            Color::rgb(
+                (i & 1) as u8 * 255,
+                (i >> 1 & 1) as u8 * 255,
+                (i >> 2 & 1) as u8 * 255,
+            )
+        })
+        .collect();
+    mesh.set_shader(Box::new(VertexColorShader));
+    check("cube_vertex_colors", &mut render(&mut mesh, 3.));
+}
+
+#[test]
+fn suzanne_points() {
+    // Without faces, meshes are drawn as point clouds
+    let mut mesh = loader::load("models/suzanne.obj").unwrap();
+    mesh.faces.clear();
+    check("suzanne_points", &mut render(&mut mesh, 3.5));
+}
+
+/// Suzanne with smooth normals (the model's own normals are flat, and so is its smoothing group)
+fn smooth_suzanne() -> Mesh {
+    let mut mesh = loader::load("models/suzanne.obj").unwrap();
+    mesh.normals.clear();
+    for face in &mut mesh.faces {
+        (face.na, face.nb, face.nc) = (None, None, None);
+        face.smoothing_group = None;
+    }
+    mesh.generate_normals(std::f32::consts::PI);
+    mesh
+}
+
+#[test]
+fn suzanne_generated_normals() {
+    let mut mesh = smooth_suzanne();
+    mesh.set_shader(Box::new(NormalShader));
+    check("suzanne_generated_normals", &mut render(&mut mesh, 3.5));
+}
+
+#[test]
+fn suzanne_per_pixel_lighting() {
+    for (lighting, name) in [
+        (Lighting::Phong, "suzanne_phong"),
+        (Lighting::BlinnPhong, "suzanne_blinn_phong"),
+    ] {
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        renderer.lighting = lighting;
+        let mut shader = StandardShader::new(Color::rgb(200, 120, 80));
+        shader.specular = Specular::new(Color::rgb(255, 255, 255), 16.);
+        let mut mesh = smooth_suzanne();
+        mesh.set_shader(Box::new(shader));
+        check(name, &mut render_with(renderer, &mut mesh, 3.5));
+    }
+}
+
+#[test]
+fn smooth_lighting_of_flat_normals_matches_flat() {
+    // The cube's normals are the normals of its faces
+    for lighting in [Lighting::Gouraud, Lighting::Phong, Lighting::BlinnPhong] {
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        renderer.lighting = lighting;
+        let mut mesh = loader::load("models/cube.obj").unwrap();
+        check("cube", &mut render_with(renderer, &mut mesh, 3.));
+    }
+}
+
+#[test]
+fn suzanne_gouraud() {
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.lighting = Lighting::Gouraud;
+    let mut mesh = smooth_suzanne();
+    mesh.set_shader(Box::new(StandardShader::new(Color::rgb(200, 120, 80))));
+    check(
+        "suzanne_gouraud",
+        &mut render_with(renderer, &mut mesh, 3.5),
+    );
+}
+
+/// Makes waves along the normals of a mesh, and colors it by how far each vertex moved
+struct WaveShader;
+
+impl Shader for WaveShader {
+    fn vertex(&self, attributes: &mut Attributes, world: &World) -> Vector4 {
+        let offset = (attributes.position.y * 12.).sin() * 0.1;
+        attributes.position = attributes.position + attributes.normal.normalised() * offset;
+        attributes.varyings[0] = offset * 5. + 0.5;
+        world.camera.project(&attributes.position)
+    }
+    fn fragment(
+        &self,
+        barycentric: &Vector3,
+        _derivatives: &Derivatives,
+        attributes: &[Attributes; 3],
+    ) -> Color {
+        let wave = Attributes::interpolate(barycentric, attributes).varyings[0];
+        Color::rgb(255, (wave * 255.).clamp(0., 255.) as u8, 64)
+    }
+}
+
+#[test]
+fn suzanne_vertex_stage() {
+    let mut mesh = smooth_suzanne();
+    mesh.set_shader(Box::new(WaveShader));
+    check("suzanne_vertex_stage", &mut render(&mut mesh, 3.5));
+}
+
+/// A floor going into the distance, with a checkerboard repeated over it
+fn floor(sampler: Sampler) -> Mesh {
+    let vertices = vec![
+        Vector3::new(-20., -0.5, -1.),
+        Vector3::new(20., -0.5, -1.),
+        Vector3::new(20., -0.5, 60.),
+        Vector3::new(-20., -0.5, 60.),
+    ];
+    let uvs = vec![
+        Vector2::new(0., 0.),
+        Vector2::new(20., 0.),
+        Vector2::new(20., 30.),
+        Vector2::new(0., 30.),
+    ];
+    let mut faces = vec![Face::new(0, 2, 1, 0, 2, 1), Face::new(0, 3, 2, 0, 3, 2)];
+    for face in &mut faces {
+        face.compute_normal(&vertices);
+    }
+    let mut shader = TextureShader::new(checkerboard(Color::rgb(40, 40, 160)));
+    shader.sampler = sampler;
+    let mut mesh = Mesh::new(vertices, faces, uvs);
+    mesh.set_shader(Box::new(shader));
+    mesh.transformation.generate_affine_matrix();
+    mesh
+}
+
+/// An 8x8 checkerboard of white and `dark` squares
+fn checkerboard(dark: Color) -> Texture {
+    let pixels = (0..64)
+        .map(|i| match (i % 8 + i / 8) % 2 {
+            0 => Color::rgb(255, 255, 255),
+            _ => dark,
+        })
+        .collect();
+    Texture::new(pixels, 8, 8).unwrap()
+}
+
+#[test]
+fn floor_mipmaps() {
+    let anisotropic = Sampler {
+        anisotropy: 8,
+        ..Sampler::trilinear(Wrap::Repeat)
+    };
+    let nearest_mipmaps = Sampler {
+        mipmaps: Mipmaps::Nearest,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    for (sampler, name) in [
+        (Sampler::new(Filter::Nearest, Wrap::Repeat), "floor_nearest"),
+        (nearest_mipmaps, "floor_nearest_mipmaps"),
+        (Sampler::trilinear(Wrap::Repeat), "floor_trilinear"),
+        (anisotropic, "floor_anisotropic"),
+    ] {
+        let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+        renderer.clear();
+        floor(sampler).draw(&mut renderer, &world(2.));
+        renderer.resolve();
+        check(name, &mut renderer);
+    }
+}
+
+#[test]
+fn floor_cutout() {
+    // The dark squares are transparent, and cut out of the floor
+    let mut shader = TextureShader::new(checkerboard(Color(40, 40, 160, 0)));
+    shader.alpha_mode = AlphaMode::Mask(0.5);
+    let mut mesh = floor(Sampler::trilinear(Wrap::Repeat));
+    mesh.set_shader(Box::new(shader));
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.clear();
+    mesh.draw(&mut renderer, &world(2.));
+    renderer.resolve();
+    check("floor_cutout", &mut renderer);
+}
+
+#[test]
+fn suzanne_glass() {
+    // Suzanne's faces overlap (ears, eyes...), so they need to be sorted to be blended right
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.lighting = Lighting::BlinnPhong;
+    let world = world(3.5);
+    let mut shader = StandardShader::new(Color(120, 200, 255, 96));
+    shader.specular = Specular::new(Color::rgb(255, 255, 255), 16.);
+    shader.alpha_mode = AlphaMode::Blend(Blend::ALPHA);
+    let mut mesh = smooth_suzanne();
+    mesh.set_shader(Box::new(shader));
+    mesh.transformation.quaternion = Vector4::new(-0.2, -0.4, 0., 1.).normalised();
+    mesh.transformation.generate_affine_matrix();
+    // The floor, stood up as a wall behind Suzanne
+    let mut wall = floor(Sampler::trilinear(Wrap::Repeat));
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    wall.transformation.quaternion = Vector4::new(-half, 0., 0., half);
+    wall.transformation.position = Vector3::new(0., -4., 2.);
+    wall.transformation.generate_affine_matrix();
+    renderer.clear();
+    wall.draw(&mut renderer, &world);
+    mesh.draw(&mut renderer, &world);
+    renderer.resolve();
+    check("suzanne_glass", &mut renderer);
+}
+
+#[test]
+fn blended_faces_are_sorted_back_to_front() {
+    // Two squares facing the camera, the nearest one first
+    let vertices = [-0.5, 0.5]
+        .into_iter()
+        .flat_map(|z| {
+            [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].map(|(x, y)| Vector3::new(x, y, z))
+        })
+        .collect::<Vec<_>>();
+    let mut faces = Vec::new();
+    for offset in [0, 4] {
+        faces.push(Face::new(offset, offset + 2, offset + 1, 0, 0, 0));
+        faces.push(Face::new(offset, offset + 3, offset + 2, 0, 0, 0));
+    }
+    for face in &mut faces {
+        face.compute_normal(&vertices);
+    }
+    let mut mesh = Mesh::new(vertices, faces, vec![Vector2::new(0., 0.)]);
+    mesh.primitives = vec![Primitive::new(0..2), Primitive::new(2..4)];
+    let (near, far) = (Color(255, 0, 0, 128), Color(0, 0, 255, 128));
+    for (primitive, color) in mesh.primitives.iter_mut().zip([near, far]) {
+        let mut shader = StandardShader::new(color);
+        shader.alpha_mode = AlphaMode::Blend(Blend::ALPHA);
+        primitive.shader = Box::new(shader);
+    }
+    mesh.transformation.generate_affine_matrix();
+
+    // The light grazes the squares, so without ambient light their colors are left as they are
+    let mut world = world(3.);
+    world.ambient = 0.;
+    let mut renderer = Renderer::headless(WIDTH, HEIGHT);
+    renderer.clear();
+    mesh.draw(&mut renderer, &world);
+    let center = (HEIGHT / 2 * WIDTH + WIDTH / 2) as usize;
+    let behind = Blend::ALPHA.apply(far, renderer.clear_color);
+    let expected = Blend::ALPHA.apply(near, behind);
+    assert_eq!(Color::from(renderer.color_buffer()[center]), expected);
+    // Blended faces don't hide what is drawn after them
+    assert!(renderer.depth_buffer()[center].is_infinite());
+}
@@ -0,0 +1,775 @@
+use std::path::{Path, PathBuf};
+
+use renderer::{
+    graphics::{AlphaMode, Attributes, Blend, Color, Derivatives},
+    loader::{self, LoadError, LoadErrorKind},
+    math::{Vector2, Vector3},
+    mesh::Mesh,
+    texture::{ColorSpace, Filter, Sampler, Texture, Wrap},
+};
+
+/// Writes an .obj file to the temporary directory and loads it
+fn try_load_obj(name: &str, contents: &str) -> Result<Mesh, LoadError> {
+    let path: PathBuf = std::env::temp_dir().join(format!("renderer-test-{}.obj", name));
+    std::fs::write(&path, contents).unwrap();
+    loader::load(path.to_str().unwrap())
+}
+
+fn load_obj(name: &str, contents: &str) -> Mesh {
+    try_load_obj(name, contents).unwrap()
+}
+
+#[test]
+fn all_index_forms() {
+    let mesh = load_obj(
+        "index_forms",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\n\
+         f 1 2 3\nf 1/1 2/2 3/3\nf 1//1 2//1 3//1\nf 1/1/1 2/2/1 3/3/1\n",
+    );
+    assert_eq!(mesh.faces.len(), 4);
+    // Faces without UVs use a placeholder added after the file's UVs
+    assert_eq!(mesh.uvs.len(), 4);
+    assert_eq!(mesh.faces[0].uva, 3);
+    assert_eq!(mesh.faces[1].uvb, 1);
+    // Corners without a normal get a generated one, after the file's normals
+    assert_eq!(mesh.faces[1].na, Some(1));
+    assert_eq!(mesh.faces[2].nc, Some(0));
+    assert_eq!(mesh.faces[3].uvc, 2);
+}
+
+#[test]
+fn negative_indices() {
+    let mesh = load_obj(
+        "negative_indices",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nf -3/-3 -2/-2 -1/-1\n\
+         v 5 5 5\nf -4 -3 -1\n",
+    );
+    let faces: Vec<_> = mesh.faces.iter().map(|f| (f.a, f.b, f.c)).collect();
+    assert_eq!(faces, vec![(0, 1, 2), (0, 1, 3)]);
+    assert_eq!(mesh.faces[0].uva, 0);
+}
+
+#[test]
+fn whitespace_comments_and_continuations() {
+    let mesh = load_obj(
+        "whitespace",
+        "# comment\nv\t0  0 0\nv 1 0 0 # trailing comment\nv 1 \\\n 1 0\n\nf  1\t2   3  \n",
+    );
+    assert_eq!(mesh.vertices.len(), 3);
+    assert_eq!(mesh.vertices[2].y, 1.);
+    assert_eq!(mesh.faces.len(), 1);
+}
+
+#[test]
+fn polygons_are_triangulated() {
+    // A concave "L" shape: a fan from the first corner would go outside of it
+    let mesh = load_obj(
+        "concave",
+        "v 0 0 0\nv 2 0 0\nv 2 1 0\nv 1 1 0\nv 1 2 0\nv 0 2 0\nf 1 2 3 4 5 6\n",
+    );
+    assert_eq!(mesh.faces.len(), 4);
+    let area: f32 = mesh
+        .faces
+        .iter()
+        .map(|face| {
+            let (a, b, c) = (
+                mesh.vertices[face.a],
+                mesh.vertices[face.b],
+                mesh.vertices[face.c],
+            );
+            // Signed, so triangles with the wrong winding would cancel out
+            ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)) / 2.
+        })
+        .sum();
+    assert_eq!(area, 3.);
+}
+
+#[test]
+fn groups_and_smoothing_groups() {
+    let mesh = load_obj(
+        "groups",
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\no first\ns 1\nf 1 2 3\nf 1 3 4\n\
+         g second part\ns off\nf 1 2 4\n",
+    );
+    assert_eq!(mesh.groups.len(), 2);
+    assert_eq!(mesh.groups[0].name, "first");
+    assert_eq!(mesh.groups[0].faces, 0..2);
+    assert_eq!(mesh.groups[1].name, "second part");
+    assert_eq!(mesh.groups[1].faces, 2..3);
+    assert_eq!(mesh.faces[1].smoothing_group, Some(1));
+    assert_eq!(mesh.faces[2].smoothing_group, Some(0));
+}
+
+/// Two faces at a right angle, sharing their first vertex
+fn hinge(smoothing_groups: [&str; 2]) -> String {
+    format!(
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\ns {}\nf 1 2 3\ns {}\nf 2 1 4\n",
+        smoothing_groups[0], smoothing_groups[1]
+    )
+}
+
+/// Normal of a corner (0, 1 or 2) of a face
+fn corner_normal(mesh: &Mesh, face: usize, corner: usize) -> Vector3 {
+    let face = &mesh.faces[face];
+    mesh.normals[[face.na, face.nb, face.nc][corner].unwrap()]
+}
+
+fn regenerate_normals(mesh: &mut Mesh, crease_angle: f32) {
+    mesh.normals.clear();
+    for face in &mut mesh.faces {
+        (face.na, face.nb, face.nc) = (None, None, None);
+    }
+    mesh.generate_normals(crease_angle);
+}
+
+#[test]
+fn generated_normals() {
+    // The faces are too far apart to be smoothed by default
+    let mut mesh = load_obj("hinge", &hinge(["1", "1"]));
+    assert!(corner_normal(&mesh, 0, 0) == mesh.faces[0].normal);
+    assert!(corner_normal(&mesh, 1, 1) == mesh.faces[1].normal);
+
+    // Faces at the same angle and of the same area contribute equally
+    regenerate_normals(&mut mesh, std::f32::consts::PI);
+    let smooth = corner_normal(&mesh, 0, 0);
+    let expected = (mesh.faces[0].normal + mesh.faces[1].normal).normalised();
+    assert!(smooth.distance_to(&expected) < 1e-6);
+    assert!(corner_normal(&mesh, 1, 1) == smooth);
+    // Corners of the first face that aren't shared keep its normal
+    assert!(corner_normal(&mesh, 0, 2) == mesh.faces[0].normal);
+
+    // Without any s statement, faces are smoothed by the crease angle only
+    let mut mesh = load_obj("hinge_no_groups", &hinge(["1", "1"]).replace("s 1\n", ""));
+    assert_eq!(mesh.faces[0].smoothing_group, None);
+    regenerate_normals(&mut mesh, std::f32::consts::PI);
+    assert!(corner_normal(&mesh, 0, 0) == smooth);
+
+    // Flat faces (s off or s 0) keep their own normal whatever the crease angle
+    let mut mesh = load_obj("hinge_off", &hinge(["off", "0"]));
+    regenerate_normals(&mut mesh, std::f32::consts::PI);
+    assert!(corner_normal(&mesh, 0, 0) == mesh.faces[0].normal);
+    assert!(corner_normal(&mesh, 1, 1) == mesh.faces[1].normal);
+
+    // Faces in different smoothing groups are never smoothed together
+    let mut mesh = load_obj("hinge_groups", &hinge(["1", "2"]));
+    regenerate_normals(&mut mesh, std::f32::consts::PI);
+    assert!(corner_normal(&mesh, 0, 0) == mesh.faces[0].normal);
+    assert!(corner_normal(&mesh, 1, 1) == mesh.faces[1].normal);
+
+    // Normals from the file are kept
+    let mesh = load_obj(
+        "hinge_normals",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 1 0 0\nf 1//1 2//1 3//1\nf 1 3 2\n",
+    );
+    assert!(corner_normal(&mesh, 0, 0) == Vector3::new(1., 0., 0.));
+    assert_eq!(mesh.faces[1].na, Some(1));
+}
+
+#[test]
+fn every_model_loads() {
+    for model in [
+        "models/cube.obj",
+        "models/nut.obj",
+        "models/suzanne.obj",
+        "models/tank.obj",
+        "models/teapot.obj",
+        "models/text.obj",
+        "models/quad_damage/quad_damage.obj",
+    ] {
+        let mesh = loader::load(model).unwrap();
+        assert!(!mesh.faces.is_empty(), "{} has no faces", model);
+    }
+}
+
+#[test]
+fn errors_have_a_line_and_reason() {
+    let error = try_load_obj("bad_float", "v 0 0 0\nv 1 zero 0\n")
+        .err()
+        .unwrap();
+    assert_eq!(error.line, Some(2));
+    assert!(matches!(error.kind, LoadErrorKind::MalformedFloat(ref value) if value == "zero"));
+
+    let error = try_load_obj("bad_index", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n")
+        .err()
+        .unwrap();
+    assert_eq!(error.line, Some(4));
+    assert!(matches!(error.kind, LoadErrorKind::BadIndex(ref index) if index == "3"));
+
+    let error = try_load_obj("zero_index", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 0 1 2\n")
+        .err()
+        .unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::BadIndex(_)));
+
+    let error = try_load_obj("bad_uv", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2 3\n")
+        .err()
+        .unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::BadIndex(ref index) if index == "1/1"));
+
+    let error = try_load_obj("missing_values", "v 0 0\n").err().unwrap();
+    assert!(matches!(
+        error.kind,
+        LoadErrorKind::MissingValues {
+            expected: 3,
+            found: 2
+        }
+    ));
+    assert!(error
+        .to_string()
+        .ends_with(":1: expected 3 values, found 2"));
+}
+
+#[test]
+fn missing_files_are_errors() {
+    let error = loader::load("models/does_not_exist.obj").err().unwrap();
+    assert_eq!(error.line, None);
+    assert!(matches!(error.kind, LoadErrorKind::Io(_)));
+
+    let error = Texture::load(Path::new("models/does_not_exist.png"))
+        .err()
+        .unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::MissingTexture(_)));
+
+    // Not an image
+    let error = Texture::load(Path::new("models/cube.obj")).err().unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::InvalidTexture(_)));
+}
+
+#[test]
+fn materials_are_read_from_mtl_files() {
+    let directory = std::env::temp_dir();
+    let texture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("models/quad_damage/texture.png");
+    std::fs::write(
+        directory.join("renderer-test-materials.mtl"),
+        format!(
+            "newmtl red\nKd 1 0 0\nKs 0.5\nNs 10\nd 0.5\n\
+             newmtl textured\nmap_Kd -s 1 1 1 {}\nmap_Bump bump.png\n",
+            texture.display()
+        ),
+    )
+    .unwrap();
+    let mesh = load_obj(
+        "materials",
+        "mtllib renderer-test-materials.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n\
+         o thing\nusemtl red\nf 1 2 3\nf 1 2 3\nusemtl textured\nf 1 2 3\n\
+         usemtl unknown\nf 1 2 3\n",
+    );
+    assert_eq!(mesh.materials.len(), 2);
+    let red = &mesh.materials[0];
+    assert_eq!(red.diffuse, Color::rgb(255, 0, 0));
+    assert_eq!(red.specular, Color::rgb(128, 128, 128));
+    assert_eq!((red.shininess, red.opacity), (10., 0.5));
+    // Materials that aren't opaque are blended
+    assert_eq!(
+        red.shader().unwrap().alpha_mode(),
+        AlphaMode::Blend(Blend::ALPHA)
+    );
+    let textured = &mesh.materials[1];
+    assert_eq!(textured.diffuse_map, Some(texture));
+    assert_eq!(textured.bump_map, Some(directory.join("bump.png")));
+    assert_eq!(textured.shader().unwrap().alpha_mode(), AlphaMode::Opaque);
+
+    // Each run of faces with the same material is a primitive
+    let primitives: Vec<_> = mesh
+        .primitives
+        .iter()
+        .map(|primitive| (primitive.faces.clone(), primitive.material))
+        .collect();
+    assert_eq!(
+        primitives,
+        vec![(0..1, None), (1..3, Some(0)), (3..4, Some(1)), (4..5, None)]
+    );
+    // Switching materials doesn't split groups
+    assert_eq!(mesh.groups.len(), 1);
+    assert_eq!(mesh.groups[0].faces, 1..5);
+}
+
+#[test]
+fn alpha_maps_cut_materials_out() {
+    // Black on the left, white on the right
+    let directory = std::env::temp_dir();
+    image::GrayImage::from_fn(2, 1, |x, _| image::Luma([x as u8 * 255]))
+        .save(directory.join("renderer-test-alpha.png"))
+        .unwrap();
+    std::fs::write(
+        directory.join("renderer-test-alpha.mtl"),
+        "newmtl leaf\nKd 0 1 0\nmap_d renderer-test-alpha.png\n",
+    )
+    .unwrap();
+    let mesh = load_obj(
+        "alpha",
+        "mtllib renderer-test-alpha.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nusemtl leaf\nf 1 2 3\n",
+    );
+    let shader = mesh.materials[0].shader().unwrap();
+    assert_eq!(shader.alpha_mode(), AlphaMode::Mask(0.5));
+    let at = |u: f32| {
+        let corner = Attributes {
+            uv: Vector2::new(u, 0.5),
+            ..Default::default()
+        };
+        shader.fragment(
+            &Vector3::new(1., 0., 0.),
+            &Derivatives::zero(),
+            &[corner; 3],
+        )
+    };
+    assert_eq!(at(0.25), Color(0, 255, 0, 0));
+    assert_eq!(at(0.75), Color(0, 255, 0, 255));
+}
+
+#[test]
+fn model_textures_come_from_materials() {
+    let mesh = loader::load("models/quad_damage/quad_damage.obj").unwrap();
+    assert_eq!(
+        mesh.materials[0].diffuse_map,
+        Some(PathBuf::from("models/quad_damage/texture.png"))
+    );
+    // Decoded once, for every shader made from the material
+    assert!(mesh.materials[0].diffuse_texture.is_some());
+    assert!(mesh.materials[0].shader().is_ok());
+}
+
+/// Binary data for a triangle: positions, normals, UVs and u16 indices
+fn triangle_buffer() -> Vec<u8> {
+    let floats: [f32; 24] = [
+        0., 0., 0., 1., 0., 0., 0., 1., 0., // positions
+        0., 0., 1., 0., 0., 1., 0., 0., 1., // normals
+        0., 0., 1., 0., 0., 1., // UVs
+    ];
+    let mut buffer: Vec<u8> = floats.iter().flat_map(|f| f.to_le_bytes()).collect();
+    buffer.extend([0u16, 1, 2, 0].iter().flat_map(|i| i.to_le_bytes()));
+    buffer
+}
+
+/// glTF document for the triangle, in a child node with a scale of 2 whose parent is moved by 1
+/// on x and rotated by 90° around y
+fn triangle_gltf(buffer: &str) -> String {
+    format!(
+        r#"{{
+  "asset": {{"version": "2.0"}},
+  "scene": 0,
+  "scenes": [{{"nodes": [0]}}],
+  "nodes": [
+    {{"name": "parent", "translation": [1, 0, 0], "rotation": [0, 0.70710677, 0, 0.70710677], "children": [1]}},
+    {{"name": "child", "scale": [2, 2, 2], "mesh": 0}}
+  ],
+  "meshes": [{{"primitives": [{{
+    "attributes": {{"POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2}},
+    "indices": 3,
+    "material": 0
+  }}]}}],
+  "materials": [{{
+    "name": "red",
+    "pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 0.5], "metallicFactor": 0.25, "roughnessFactor": 0.75}},
+    "alphaMode": "BLEND"
+  }}],
+  "buffers": [{{{}"byteLength": 104}}],
+  "bufferViews": [
+    {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 36, "byteLength": 36}},
+    {{"buffer": 0, "byteOffset": 72, "byteLength": 24}},
+    {{"buffer": 0, "byteOffset": 96, "byteLength": 6}}
+  ],
+  "accessors": [
+    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+    {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3"}},
+    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2"}},
+    {{"bufferView": 3, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+  ]
+}}"#,
+        buffer
+    )
+}
+
+fn check_triangle(meshes: &[Mesh]) {
+    assert_eq!(meshes.len(), 1);
+    let mesh = &meshes[0];
+    assert_eq!(mesh.groups[0].name, "child");
+    assert_eq!(mesh.faces.len(), 1);
+    assert_eq!(mesh.faces[0].nc, Some(2));
+    assert_eq!(mesh.uvs[1].x, 1.);
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+    // The scale is applied to the vertices...
+    assert!(close(mesh.vertices[1].x, 2.) && close(mesh.vertices[2].y, 2.));
+    // ...and the parent's rotation and translation go in the transformation
+    let moved = mesh.transformation.transformed(&mesh.vertices[1]);
+    assert!(close(moved.x, 1.) && close(moved.y, 0.) && close(moved.z, -2.));
+
+    let primitive = &mesh.primitives[0];
+    assert_eq!(
+        (primitive.faces.clone(), primitive.material),
+        (0..1, Some(0))
+    );
+    assert_eq!(
+        primitive.shader.alpha_mode(),
+        AlphaMode::Blend(Blend::ALPHA)
+    );
+    let material = &mesh.materials[0];
+    assert_eq!(material.name, "red");
+    assert_eq!(material.diffuse, Color::rgb(255, 0, 0));
+    assert_eq!(
+        (material.opacity, material.alpha_mode),
+        (0.5, AlphaMode::Blend(Blend::ALPHA))
+    );
+    assert_eq!((material.metallic, material.roughness), (0.25, 0.75));
+}
+
+#[test]
+fn gltf_with_external_buffer() {
+    let directory = std::env::temp_dir();
+    std::fs::write(
+        directory.join("renderer-test-triangle.bin"),
+        triangle_buffer(),
+    )
+    .unwrap();
+    let path = directory.join("renderer-test-triangle.gltf");
+    std::fs::write(
+        &path,
+        triangle_gltf(r#""uri": "renderer-test-triangle.bin", "#),
+    )
+    .unwrap();
+    check_triangle(&loader::load_gltf(path.to_str().unwrap()).unwrap());
+}
+
+/// Writes a .glb file to the temporary directory. Chunks are padded to 4 bytes, with spaces for
+/// JSON and zeros for binary data.
+fn write_glb(name: &str, json: String, mut binary: Vec<u8>) -> PathBuf {
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    binary.resize(binary.len().next_multiple_of(4), 0);
+
+    let mut glb = Vec::new();
+    glb.extend(b"glTF");
+    glb.extend(2u32.to_le_bytes());
+    glb.extend(((12 + 8 + json.len() + 8 + binary.len()) as u32).to_le_bytes());
+    glb.extend((json.len() as u32).to_le_bytes());
+    glb.extend(b"JSON");
+    glb.extend(json);
+    glb.extend((binary.len() as u32).to_le_bytes());
+    glb.extend(b"BIN\0");
+    glb.extend(binary);
+
+    let path = std::env::temp_dir().join(format!("renderer-test-{}.glb", name));
+    std::fs::write(&path, glb).unwrap();
+    path
+}
+
+#[test]
+fn glb_with_embedded_buffer() {
+    let path = write_glb("triangle", triangle_gltf(""), triangle_buffer());
+    check_triangle(&loader::load_gltf(path.to_str().unwrap()).unwrap());
+}
+
+#[test]
+fn glb_with_embedded_texture() {
+    // A 2x1 green PNG after the triangle's data
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(2, 1, image::Rgb([0, 255, 0]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let mut binary = triangle_buffer();
+    let json = triangle_gltf("")
+        .replace(
+            r#""metallicFactor""#,
+            r#""baseColorTexture": {"index": 0}, "metallicRoughnessTexture": {"index": 0},
+      "metallicFactor""#,
+        )
+        .replace(
+            r#""buffers""#,
+            r#""images": [{"bufferView": 4, "mimeType": "image/png"}],
+  "textures": [{"source": 0, "sampler": 0}],
+  "samplers": [{"magFilter": 9728, "minFilter": 9728, "wrapS": 33071, "wrapT": 33071}],
+  "buffers""#,
+        )
+        .replace(
+            r#""byteLength": 104}"#,
+            &format!(r#""byteLength": {}}}"#, binary.len() + png.len()),
+        )
+        .replace(
+            r#""byteLength": 6}"#,
+            &format!(
+                r#""byteLength": 6}},
+    {{"buffer": 0, "byteOffset": {}, "byteLength": {}}}"#,
+                binary.len(),
+                png.len()
+            ),
+        );
+    binary.extend(png);
+    let path = write_glb("textured-triangle", json, binary);
+
+    let meshes = loader::load_gltf(path.to_str().unwrap()).unwrap();
+    let material = &meshes[0].materials[0];
+    // Embedded images have no path, but are kept decoded for the shader
+    assert_eq!(material.diffuse_map, None);
+    let texture = material.diffuse_texture.as_ref().unwrap();
+    assert_eq!((texture.width(), texture.height()), (2, 1));
+    assert_eq!(texture.color_space(), ColorSpace::Srgb);
+    assert_eq!(
+        material.sampler,
+        Sampler::new(Filter::Nearest, Wrap::ClampToEdge)
+    );
+    // The same image holds data for the metallic and roughness texture
+    let data = material.metallic_roughness_texture.as_ref().unwrap();
+    assert_eq!(data.color_space(), ColorSpace::Linear);
+    assert!(texture.levels() > 1 && data.levels() > 1);
+
+    let shader = material.shader().unwrap();
+    let corner = Attributes {
+        uv: Vector2::new(0.25, 0.5),
+        ..Default::default()
+    };
+    // The green texture tinted by the red base color
+    assert_eq!(
+        shader.fragment(
+            &Vector3::new(1., 0., 0.),
+            &Derivatives::zero(),
+            &[corner; 3]
+        ),
+        Color(0, 0, 0, 128)
+    );
+}
+
+#[test]
+fn gltf_attributes_need_a_value_per_position() {
+    let directory = std::env::temp_dir();
+    std::fs::write(directory.join("renderer-test-short.bin"), triangle_buffer()).unwrap();
+    // Two UVs for three positions
+    let path = directory.join("renderer-test-short.gltf");
+    std::fs::write(
+        &path,
+        triangle_gltf(r#""uri": "renderer-test-short.bin", "#).replace(
+            r#""count": 3, "type": "VEC2""#,
+            r#""count": 2, "type": "VEC2""#,
+        ),
+    )
+    .unwrap();
+    let error = loader::load_gltf(path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(
+        error.kind,
+        LoadErrorKind::MissingValues {
+            expected: 3,
+            found: 2
+        }
+    ));
+}
+
+#[test]
+fn invalid_gltf_is_an_error() {
+    let path = std::env::temp_dir().join("renderer-test-invalid.gltf");
+    std::fs::write(&path, "{\"asset\": {}}").unwrap();
+    let error = loader::load_gltf(path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::Gltf(_)));
+}
+
+#[test]
+fn ascii_stl() {
+    let path = std::env::temp_dir().join("renderer-test-ascii.stl");
+    // A square in two solids; the second facet is wound the wrong way for its normal
+    std::fs::write(
+        &path,
+        "solid first\n\
+         facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 1 1 0\n \
+         endloop\nendfacet\nendsolid first\n\
+         solid second\n\
+         facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 0 1 0\n  vertex 1 1 0\n \
+         endloop\nendfacet\nendsolid second\n",
+    )
+    .unwrap();
+    let mesh = loader::load_stl(path.to_str().unwrap()).unwrap();
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.faces.len(), 2);
+    for face in &mesh.faces {
+        assert_eq!(face.normal.z, 1.);
+        assert_eq!(mesh.normals[face.na.unwrap()].z, 1.);
+    }
+    assert_eq!(mesh.groups.len(), 2);
+    assert_eq!(mesh.groups[1].name, "second");
+    assert_eq!(mesh.groups[1].faces, 1..2);
+}
+
+#[test]
+fn binary_stl() {
+    let mut data = b"solid but actually binary".to_vec();
+    data.resize(80, 0);
+    data.extend(2u32.to_le_bytes());
+    let facets: [[f32; 12]; 2] = [
+        [0., 0., 1., 0., 0., 0., 1., 0., 0., 1., 1., 0.],
+        // No normal, so the winding is kept
+        [0., 0., 0., 0., 0., 0., 1., 1., 0., 0., 1., 0.],
+    ];
+    for facet in facets {
+        data.extend(facet.iter().flat_map(|f| f.to_le_bytes()));
+        data.extend([0, 0]);
+    }
+    let path = std::env::temp_dir().join("renderer-test-binary.stl");
+    std::fs::write(&path, &data).unwrap();
+    let mesh = loader::load_stl(path.to_str().unwrap()).unwrap();
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.faces.len(), 2);
+    assert_eq!(mesh.faces[1].normal.z, 1.);
+    assert_eq!(mesh.faces[1].na, None);
+
+    // Truncated
+    std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+    let error = loader::load_stl(path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::UnexpectedEnd));
+}
+
+/// A colored square as a PLY file, with an extra element that has to be skipped
+fn square_ply(format: &str) -> Vec<u8> {
+    let mut ply = format!(
+        "ply\nformat {} 1.0\ncomment made by hand\nelement vertex 4\n\
+         property float x\nproperty float y\nproperty float z\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\n\
+         element face 1\nproperty list uchar int vertex_indices\n\
+         element edge 1\nproperty int vertex1\nproperty int vertex2\nend_header\n",
+        format
+    )
+    .into_bytes();
+    let vertices = [
+        ([0f32, 0., 0.], [255u8, 0, 0]),
+        ([1., 0., 0.], [0, 255, 0]),
+        ([1., 1., 0.], [0, 0, 255]),
+        ([0., 1., 0.], [255, 255, 255]),
+    ];
+    let big_endian = format == "binary_big_endian";
+    let int = |value: i32| {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+    if format == "ascii" {
+        for (position, color) in vertices {
+            ply.extend(
+                format!(
+                    "{} {} {} {} {} {}\n",
+                    position[0], position[1], position[2], color[0], color[1], color[2]
+                )
+                .bytes(),
+            );
+        }
+        ply.extend(b"4 0 1 2 3\n0 1\n");
+    } else {
+        for (position, color) in vertices {
+            for value in position {
+                ply.extend(if big_endian {
+                    value.to_be_bytes()
+                } else {
+                    value.to_le_bytes()
+                });
+            }
+            ply.extend(color);
+        }
+        ply.push(4);
+        for index in [0, 1, 2, 3, 0, 1] {
+            ply.extend(int(index));
+        }
+    }
+    ply
+}
+
+#[test]
+fn ply_formats() {
+    for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+        let path = std::env::temp_dir().join(format!("renderer-test-{}.ply", format));
+        std::fs::write(&path, square_ply(format)).unwrap();
+        let mesh = loader::load_ply(path.to_str().unwrap()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4, "{}", format);
+        assert_eq!(mesh.vertices[2].y, 1., "{}", format);
+        assert_eq!(mesh.faces.len(), 2, "{}", format);
+        assert_eq!(
+            mesh.colors,
+            vec![
+                Color::rgb(255, 0, 0),
+                Color::rgb(0, 255, 0),
+                Color::rgb(0, 0, 255),
+                Color::rgb(255, 255, 255)
+            ],
+            "{}",
+            format
+        );
+    }
+}
+
+#[test]
+fn ply_point_cloud() {
+    let path = std::env::temp_dir().join("renderer-test-points.ply");
+    std::fs::write(
+        &path,
+        "ply\nformat ascii 1.0\nelement vertex 2\nproperty double x\nproperty double y\n\
+         property double z\nproperty float nx\nproperty float ny\nproperty float nz\n\
+         end_header\n0 0 0 0 0 1\n1 2 3 0 1 0\n",
+    )
+    .unwrap();
+    let mesh = loader::load_ply(path.to_str().unwrap()).unwrap();
+    assert_eq!(mesh.vertices.len(), 2);
+    assert!(mesh.faces.is_empty());
+    assert_eq!(mesh.normals[1].y, 1.);
+    assert!(mesh.colors.is_empty());
+}
+
+#[test]
+fn ply_vertex_alpha() {
+    let path = std::env::temp_dir().join("renderer-test-alpha.ply");
+    std::fs::write(
+        &path,
+        "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+         property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+         property uchar alpha\nend_header\n0 0 0 255 0 0 128\n1 0 0 0 255 0 255\n",
+    )
+    .unwrap();
+    let mesh = loader::load_ply(path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        mesh.colors,
+        vec![Color(255, 0, 0, 128), Color::rgb(0, 255, 0)]
+    );
+}
+
+#[test]
+fn ply_16_bit_colors() {
+    let path = std::env::temp_dir().join("renderer-test-16-bit.ply");
+    std::fs::write(
+        &path,
+        "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\n\
+         property float z\nproperty ushort red\nproperty ushort green\nproperty ushort blue\n\
+         end_header\n0 0 0 65535 32896 255\n",
+    )
+    .unwrap();
+    let mesh = loader::load_ply(path.to_str().unwrap()).unwrap();
+    // Scaled by the largest ushort rather than clamped to 255
+    assert_eq!(mesh.colors, vec![Color::rgb(255, 128, 1)]);
+}
+
+#[test]
+fn ply_errors() {
+    let path = std::env::temp_dir().join("renderer-test-bad.ply");
+    std::fs::write(
+        &path,
+        "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\nnope\n",
+    )
+    .unwrap();
+    let error = loader::load_ply(path.to_str().unwrap()).err().unwrap();
+    assert_eq!(error.line, Some(6));
+    assert!(matches!(error.kind, LoadErrorKind::MalformedFloat(_)));
+
+    let mut truncated = square_ply("binary_little_endian");
+    truncated.truncate(truncated.len() - 10);
+    std::fs::write(&path, truncated).unwrap();
+    let error = loader::load_ply(path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(error.kind, LoadErrorKind::UnexpectedEnd));
+
+    // Negative and fractional indices don't wrap around or get rounded to a vertex
+    for (kind, face, index) in [("int", "3 0 1 -1", "-1"), ("float", "3 0 1 1.5", "1.5")] {
+        let ply = format!(
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar {} vertex_indices\n\
+             end_header\n0 0 0\n1 0 0\n1 1 0\n{}\n",
+            kind, face
+        );
+        std::fs::write(&path, ply).unwrap();
+        let error = loader::load_ply(path.to_str().unwrap()).err().unwrap();
+        assert_eq!(error.line, Some(13));
+        assert!(matches!(error.kind, LoadErrorKind::BadIndex(bad) if bad == index));
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use renderer::{
+    camera::PerspectiveCamera,
+    graphics::{
+        AlphaMode, Attributes, Blend, BlendEquation, BlendFactor, Color, Derivatives, Msaa,
+        Renderer, Triangle,
+    },
+    math::Vector3,
+    shaders::{Shader, StandardShader},
+    world::{DirectionalLight, World},
+};
+
+/// Counts the fragments that pass the depth test
+struct CountingShader {
+    fragments: AtomicUsize,
+}
+
+impl Shader for CountingShader {
+    fn fragment(
+        &self,
+        _barycentric: &Vector3,
+        _derivatives: &Derivatives,
+        _attributes: &[Attributes; 3],
+    ) -> Color {
+        self.fragments.fetch_add(1, Ordering::Relaxed);
+        Color::rgb(255, 255, 255)
+    }
+}
+
+type Point = (f32, f32);
+
+fn triangle(a: Point, b: Point, c: Point, depth: f32) -> Triangle {
+    Triangle {
+        a: Vector3::new(a.0, a.1, depth),
+        b: Vector3::new(b.0, b.1, depth),
+        c: Vector3::new(c.0, c.1, depth),
+        attributes: [Attributes {
+            normal: Vector3::new(0., 0., 1.),
+            ..Default::default()
+        }; 3],
+        w: [1.; 3],
+        brightness: 1.,
+    }
+}
+
+fn world() -> World {
+    World {
+        camera: PerspectiveCamera::new(&Vector3::new(0., 0., 0.), 64., 64.),
+        light: DirectionalLight::new(&Vector3::new(0., -1., 0.), 0.5),
+        ambient: 0.2,
+    }
+}
+
+/// Draws a list of triangles, each one in front of the previous ones so that the depth test
+/// doesn't hide pixels that are drawn twice, and returns the number of fragments
+fn count_fragments(triangles: &[(Point, Point, Point)]) -> usize {
+    let mut renderer = Renderer::headless(64, 64);
+    renderer.clear();
+    let shader = CountingShader {
+        fragments: AtomicUsize::new(0),
+    };
+    let world = world();
+    for (i, (a, b, c)) in triangles.iter().enumerate() {
+        let triangle = triangle(*a, *b, *c, 1. - i as f32 * 0.1);
+        renderer.draw_triangle(&triangle, &shader, &world);
+    }
+    shader.fragments.load(Ordering::Relaxed)
+}
+
+#[test]
+fn shared_edges_are_covered_once() {
+    // A 20x10 rectangle split along its diagonal
+    let fragments = count_fragments(&[
+        ((10., 10.), (30., 10.), (30., 20.)),
+        ((10., 10.), (30., 20.), (10., 20.)),
+    ]);
+    assert_eq!(fragments, 200);
+}
+
+#[test]
+fn fans_have_no_cracks_or_overlaps() {
+    // A 16x16 square split in four around its center, with sub-pixel vertices
+    let center = (24.3, 24.7);
+    let corners = [(16.5, 16.5), (32.5, 16.5), (32.5, 32.5), (16.5, 32.5)];
+    let fan: Vec<_> = (0..4)
+        .map(|i| (center, corners[i], corners[(i + 1) % 4]))
+        .collect();
+    assert_eq!(count_fragments(&fan), 256);
+}
+
+#[test]
+fn both_windings_are_rasterized() {
+    let clockwise = count_fragments(&[((10., 10.), (30., 10.), (30., 20.))]);
+    let counter_clockwise = count_fragments(&[((10., 10.), (30., 20.), (30., 10.))]);
+    assert_eq!(clockwise, counter_clockwise);
+    assert!(clockwise > 0);
+}
+
+#[test]
+fn fxaa_is_applied_once_per_frame() {
+    let triangles = [
+        triangle((10., 10.), (30., 12.), (30., 20.), 0.5),
+        triangle((40., 40.), (60., 42.), (60., 60.), 0.5),
+    ];
+    let shader = StandardShader::new(Color::rgb(255, 255, 255));
+    let mut once = Renderer::headless(64, 64);
+    once.fxaa = true;
+    once.clear();
+    for triangle in &triangles {
+        once.draw_triangle(triangle, &shader, &world());
+    }
+    once.resolve();
+    // Resolving in between doesn't blur the first triangle twice
+    let mut twice = Renderer::headless(64, 64);
+    twice.fxaa = true;
+    twice.clear();
+    for triangle in &triangles {
+        twice.draw_triangle(triangle, &shader, &world());
+        twice.resolve();
+    }
+    assert!(once.color_buffer() == twice.color_buffer());
+    assert!(once.read_pixels().iter().any(|channel| *channel != 0));
+}
+
+#[test]
+fn reading_pixels_resolves_the_frame() {
+    let triangle = triangle((10., 10.), (30., 10.), (30., 20.), 0.5);
+    let shader = StandardShader::new(Color::rgb(255, 255, 255));
+    let draw = || {
+        let mut renderer = Renderer::headless(64, 64);
+        renderer.set_msaa(Msaa::X4);
+        renderer.fxaa = true;
+        renderer.clear();
+        renderer.draw_triangle(&triangle, &shader, &world());
+        renderer
+    };
+    let mut resolved = draw();
+    resolved.resolve();
+    let expected: Vec<u8> = resolved
+        .color_buffer()
+        .iter()
+        .flat_map(|pixel| {
+            let color = Color::from(*pixel);
+            [color.0, color.1, color.2]
+        })
+        .collect();
+    assert!(expected.iter().any(|channel| *channel != 0));
+    assert_eq!(draw().read_pixels(), expected);
+
+    let path = std::env::temp_dir().join("renderer-test-unresolved.png");
+    draw().save_frame(&path).unwrap();
+    assert_eq!(image::open(&path).unwrap().to_rgb8().into_raw(), expected);
+}
+
+#[test]
+fn alpha_test_discards_fragments_before_they_are_written() {
+    let triangle = triangle((10., 10.), (30., 10.), (30., 20.), 0.5);
+    let mut shader = StandardShader::new(Color(255, 255, 255, 100));
+    for (cutoff, drawn) in [(0.5, false), (0.25, true)] {
+        let mut renderer = Renderer::headless(64, 64);
+        renderer.clear();
+        shader.alpha_mode = AlphaMode::Mask(cutoff);
+        renderer.draw_triangle(&triangle, &shader, &world());
+        let index = 15 * 64 + 25;
+        assert_eq!(renderer.color_buffer()[index] != 0, drawn);
+        assert_eq!(renderer.depth_buffer()[index] == 0.5, drawn);
+    }
+}
+
+#[test]
+fn blend_equations() {
+    let (source, destination) = (Color(200, 100, 0, 64), Color::rgb(100, 100, 100));
+    let blend = |blend: Blend| blend.apply(source, destination);
+    assert_eq!(blend(Blend::ALPHA), Color::rgb(125, 100, 75));
+    assert_eq!(blend(Blend::ADDITIVE), Color::rgb(150, 125, 100));
+    assert_eq!(blend(Blend::MULTIPLY), Color::rgb(78, 39, 0));
+    let (one, zero) = (BlendFactor::One, BlendFactor::Zero);
+    assert_eq!(
+        blend(Blend::new(BlendEquation::Subtract, one, one)),
+        Color::rgb(100, 0, 0)
+    );
+    assert_eq!(
+        blend(Blend::new(BlendEquation::ReverseSubtract, one, one)),
+        Color::rgb(0, 0, 100)
+    );
+    assert_eq!(
+        blend(Blend::new(
+            BlendEquation::Add,
+            zero,
+            BlendFactor::SourceColor
+        )),
+        Color::rgb(78, 39, 0)
+    );
+    // Factors don't matter for the smallest and largest
+    assert_eq!(
+        blend(Blend::new(BlendEquation::Min, zero, zero)),
+        Color::rgb(100, 100, 0)
+    );
+    assert_eq!(
+        blend(Blend::new(BlendEquation::Max, zero, zero)),
+        Color::rgb(200, 100, 100)
+    );
+}
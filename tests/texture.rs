@@ -0,0 +1,299 @@
+use std::path::Path;
+
+use image::{GrayAlphaImage, RgbImage, RgbaImage};
+use renderer::{
+    graphics::Color,
+    math::Vector2,
+    texture::{ColorSpace, Filter, Mipmaps, Sampler, Texture, Wrap},
+};
+
+const BLACK: Color = Color::rgb(0, 0, 0);
+const WHITE: Color = Color::rgb(255, 255, 255);
+
+/// A 2x2 texture, black on the left and white on the right, a bit of red on the bottom
+fn texture() -> Texture {
+    linear(
+        vec![BLACK, WHITE, Color::rgb(100, 0, 0), Color::rgb(255, 0, 0)],
+        2,
+        2,
+    )
+}
+
+/// Creates a texture that isn't gamma encoded, so its mipmaps are plain averages
+fn linear(pixels: Vec<Color>, width: u32, height: u32) -> Texture {
+    let image = RgbImage::from_fn(width, height, |x, y| {
+        let Color(r, g, b, _) = pixels[(x + y * width) as usize];
+        image::Rgb([r, g, b])
+    });
+    Texture::from_image(&image.into(), ColorSpace::Linear)
+}
+
+#[test]
+fn nearest_wraps_uvs_outside_of_the_texture() {
+    let texture = texture();
+    let sample = |u: f32, v: f32, wrap: Wrap| {
+        texture.sample(&Vector2::new(u, v), &Sampler::new(Filter::Nearest, wrap))
+    };
+    assert_eq!(sample(0.25, 0.25, Wrap::Repeat), BLACK);
+    assert_eq!(sample(0.75, 0.25, Wrap::Repeat), WHITE);
+    assert_eq!(sample(0.75, 0.75, Wrap::Repeat), Color::rgb(255, 0, 0));
+
+    // Tiles to the right, to the left and below
+    assert_eq!(sample(3.25, 0.25, Wrap::Repeat), BLACK);
+    assert_eq!(sample(-0.25, 0.25, Wrap::Repeat), WHITE);
+    assert_eq!(sample(0.25, 5.75, Wrap::Repeat), Color::rgb(100, 0, 0));
+
+    // Every other tile is flipped
+    assert_eq!(sample(1.25, 0.25, Wrap::MirroredRepeat), WHITE);
+    assert_eq!(sample(2.25, 0.25, Wrap::MirroredRepeat), BLACK);
+    assert_eq!(sample(-0.25, 0.25, Wrap::MirroredRepeat), BLACK);
+
+    assert_eq!(sample(7., -3., Wrap::ClampToEdge), WHITE);
+    assert_eq!(sample(-7., 1., Wrap::ClampToEdge), Color::rgb(100, 0, 0));
+
+    // Nothing to sample
+    assert_eq!(sample(f32::NAN, 0., Wrap::Repeat), BLACK);
+    let empty = Texture::new(Vec::new(), 0, 0).unwrap();
+    let sampler = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+    assert_eq!(empty.sample(&Vector2::new(0.5, 0.5), &sampler), BLACK);
+}
+
+#[test]
+fn bilinear_blends_the_closest_texels() {
+    let texture = texture();
+    let sample = |u: f32, v: f32, wrap: Wrap| {
+        texture.sample(&Vector2::new(u, v), &Sampler::new(Filter::Bilinear, wrap))
+    };
+    // On the centers of texels
+    assert_eq!(sample(0.25, 0.25, Wrap::ClampToEdge), BLACK);
+    assert_eq!(sample(0.75, 0.25, Wrap::ClampToEdge), WHITE);
+    // Halfway between the centers of the top texels, and of the four texels
+    assert_eq!(
+        sample(0.5, 0.25, Wrap::ClampToEdge),
+        Color::rgb(128, 128, 128)
+    );
+    assert_eq!(sample(0.5, 0.5, Wrap::ClampToEdge), Color::rgb(153, 64, 64));
+    // Edges blend with the texels on the other side when repeating, not when clamping
+    assert_eq!(sample(0., 0.25, Wrap::ClampToEdge), BLACK);
+    assert_eq!(sample(0., 0.25, Wrap::Repeat), Color::rgb(128, 128, 128));
+    assert_eq!(sample(0., 0.25, Wrap::MirroredRepeat), BLACK);
+}
+
+#[test]
+fn samplers_wrap_u_and_v_separately() {
+    let sampler = Sampler {
+        wrap_v: Wrap::ClampToEdge,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    let color = texture().sample(&Vector2::new(1.25, -2.), &sampler);
+    assert_eq!(color, BLACK);
+    let color = texture().sample(&Vector2::new(1.75, 3.), &sampler);
+    assert_eq!(color, Color::rgb(255, 0, 0));
+}
+
+#[test]
+fn mipmaps_halve_the_texture_down_to_a_texel() {
+    let mut odd = Texture::new(vec![Color::rgb(90, 0, 30); 5 * 3], 5, 3).unwrap();
+    assert_eq!(odd.levels(), 1);
+    odd.generate_mipmaps();
+    let sizes: Vec<(usize, usize)> = (0..odd.levels())
+        .map(|level| odd.level_size(level))
+        .collect();
+    assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+
+    // The last mipmap is the average of the texture
+    let mut texture = texture();
+    texture.generate_mipmaps();
+    assert_eq!(texture.levels(), 2);
+    let sampler = Sampler::trilinear(Wrap::Repeat);
+    let (uv, far) = (Vector2::new(0.25, 0.25), Vector2::new(4., 0.));
+    assert_eq!(
+        texture.sample_gradient(&uv, &far, &far, &sampler),
+        Color::rgb(153, 64, 64)
+    );
+    // Close up, only the full size texture is used
+    let close = Vector2::new(0.1, 0.);
+    assert_eq!(
+        texture.sample_gradient(&uv, &close, &close, &sampler),
+        BLACK
+    );
+}
+
+#[test]
+fn mipmaps_are_picked_by_how_many_texels_a_pixel_covers() {
+    // A black column and three white ones: the first texel is black in the texture, dark gray in
+    // the first mipmap and light gray in the last one
+    let mut texture = linear(
+        (0..16)
+            .map(|i| if i % 4 == 0 { BLACK } else { WHITE })
+            .collect(),
+        4,
+        4,
+    );
+    texture.generate_mipmaps();
+    assert_eq!(texture.levels(), 3);
+    let uv = Vector2::new(0.125, 0.125);
+    let sample = |texels: f32, sampler: &Sampler| {
+        let d = Vector2::new(texels / 4., 0.);
+        texture.sample_gradient(&uv, &d, &d, sampler)
+    };
+    let nearest = Sampler {
+        mipmaps: Mipmaps::Nearest,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    assert_eq!(sample(1., &nearest), BLACK);
+    assert_eq!(sample(2., &nearest), Color::rgb(128, 128, 128));
+    assert_eq!(sample(4., &nearest), Color::rgb(192, 192, 192));
+    assert_eq!(sample(100., &nearest), Color::rgb(192, 192, 192));
+    // Halfway between the first two mipmaps
+    let linear = Sampler {
+        mipmaps: Mipmaps::Linear,
+        ..nearest
+    };
+    assert_eq!(sample(2f32.sqrt(), &linear), Color::rgb(64, 64, 64));
+
+    // Without mipmaps, or with a texture that doesn't have any, the texture is sampled as is
+    let checkerboard = Texture::new(vec![BLACK, WHITE, WHITE, BLACK], 2, 2).unwrap();
+    let sampler = Sampler::new(Filter::Nearest, Wrap::Repeat);
+    let far = Vector2::new(10., 0.);
+    let uv = Vector2::new(0.75, 0.25);
+    assert_eq!(
+        checkerboard.sample_gradient(&uv, &far, &far, &sampler),
+        WHITE
+    );
+    let sampler = Sampler::trilinear(Wrap::Repeat);
+    assert_eq!(
+        checkerboard.sample_gradient(&uv, &far, &far, &sampler),
+        checkerboard.sample(&uv, &sampler)
+    );
+}
+
+#[test]
+fn anisotropic_filtering_samples_along_the_footprint() {
+    // Stripes of black and white columns, seen at a grazing angle: pixels cover 8 texels
+    // vertically (along the stripes) but only one horizontally
+    let mut texture = linear(
+        (0..64)
+            .map(|i| if i % 2 == 0 { BLACK } else { WHITE })
+            .collect(),
+        8,
+        8,
+    );
+    texture.generate_mipmaps();
+    let (uv, dx, dy) = (
+        Vector2::new(1. / 16., 0.5),
+        Vector2::new(1. / 8., 0.),
+        Vector2::new(0., 1.),
+    );
+    let nearest = Sampler {
+        mipmaps: Mipmaps::Nearest,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    // The stripes are blurred away by the mipmap for the longest side
+    assert_eq!(
+        texture.sample_gradient(&uv, &dx, &dy, &nearest),
+        Color::rgb(128, 128, 128)
+    );
+    let anisotropic = Sampler {
+        anisotropy: 8,
+        ..nearest
+    };
+    assert_eq!(texture.sample_gradient(&uv, &dx, &dy, &anisotropic), BLACK);
+}
+
+#[test]
+fn textures_know_their_channels_and_color_space() {
+    let texture = Texture::load(Path::new("models/quad_damage/texture.png")).unwrap();
+    assert_eq!(texture.width(), 308);
+    assert_eq!(texture.height(), 121);
+    assert_eq!(texture.channels(), 4);
+    assert!(texture.has_alpha());
+    assert_eq!(texture.color_space(), ColorSpace::Srgb);
+    assert_eq!(Texture::new(vec![WHITE], 1, 1).unwrap().channels(), 3);
+    assert!(Texture::new(vec![WHITE, Color(0, 0, 0, 0)], 2, 1)
+        .unwrap()
+        .has_alpha());
+
+    let path = std::env::temp_dir().join("renderer-test-rgba.png");
+    RgbaImage::from_pixel(3, 2, image::Rgba([10, 20, 30, 128]))
+        .save(&path)
+        .unwrap();
+    let texture = Texture::load(&path).unwrap();
+    assert_eq!((texture.width(), texture.height()), (3, 2));
+    assert_eq!(texture.channels(), 4);
+    assert!(texture.has_alpha());
+    let sampler = Sampler::new(Filter::Nearest, Wrap::Repeat);
+    assert_eq!(texture.texel(1, 1, &sampler), Color(10, 20, 30, 128));
+
+    // Gray images give gray colors
+    let image = GrayAlphaImage::from_pixel(1, 1, image::LumaA([70, 255])).into();
+    let texture = Texture::from_image(&image, ColorSpace::Linear);
+    assert_eq!(texture.channels(), 2);
+    assert_eq!(texture.color_space(), ColorSpace::Linear);
+    assert_eq!(texture.texel(0, 0, &sampler), Color::rgb(70, 70, 70));
+}
+
+#[test]
+fn srgb_mipmaps_are_averaged_in_linear_space() {
+    let mut srgb = Texture::new(vec![BLACK, WHITE], 2, 1).unwrap();
+    srgb.generate_mipmaps();
+    let mut linear = Texture::from_image(
+        &RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8 * 255, 0, 0, 255])).into(),
+        ColorSpace::Linear,
+    );
+    linear.generate_mipmaps();
+    let sampler = Sampler {
+        mipmaps: Mipmaps::Nearest,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    let (uv, far) = (Vector2::new(0.5, 0.5), Vector2::new(2., 0.));
+    assert_eq!(
+        srgb.sample_gradient(&uv, &far, &far, &sampler),
+        Color::rgb(188, 188, 188)
+    );
+    assert_eq!(
+        linear.sample_gradient(&uv, &far, &far, &sampler),
+        Color::rgb(128, 0, 0)
+    );
+}
+
+#[test]
+fn mipmaps_weight_colors_by_alpha() {
+    // Opaque red next to transparent green, which must not tint the red
+    let mut texture = Texture::from_image(
+        &RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 255, 0, 0])
+            .unwrap()
+            .into(),
+        ColorSpace::Linear,
+    );
+    texture.generate_mipmaps();
+    let sampler = Sampler {
+        mipmaps: Mipmaps::Nearest,
+        ..Sampler::new(Filter::Nearest, Wrap::Repeat)
+    };
+    let (uv, far) = (Vector2::new(0.5, 0.5), Vector2::new(2., 0.));
+    assert_eq!(
+        texture.sample_gradient(&uv, &far, &far, &sampler),
+        Color(255, 0, 0, 128)
+    );
+
+    // Fully transparent texels keep their average color
+    let mut clear = Texture::new(vec![Color(255, 0, 0, 0), Color(0, 0, 255, 0)], 2, 1).unwrap();
+    clear.set_color_space(ColorSpace::Linear);
+    clear.generate_mipmaps();
+    assert_eq!(
+        clear.sample_gradient(&uv, &far, &far, &sampler),
+        Color(128, 0, 128, 0)
+    );
+}
+
+#[test]
+fn textures_need_a_pixel_per_texel() {
+    assert!(Texture::new(vec![WHITE; 3], 2, 2).is_err());
+    assert!(Texture::new(vec![WHITE; 5], 2, 2).is_err());
+
+    // Empty textures don't have mipmaps
+    let mut empty = Texture::new(Vec::new(), 0, 4).unwrap();
+    empty.generate_mipmaps();
+    assert_eq!(empty.levels(), 1);
+}